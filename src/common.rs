@@ -16,15 +16,31 @@ use std::fmt;
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum TokenType {
     Move,
+    MoveZeroExtend,
+    MoveSignExtend,
+    MoveIf,
     Add,
     Subtract,
     Multiply,
-    JumpIfEqual,
-    JumpIfNotEqual,
-    JumpIfLess,
-    JumpIfLessEqual,
-    JumpIfGreater,
-    JumpIfGreaterEqual,
+    Divide,
+    DivideUnsigned,
+    Remainder,
+    RemainderUnsigned,
+    And,
+    Or,
+    Xor,
+    Not,
+    ShiftLeft,
+    ShiftRight,
+    ShiftRightArithmetic,
+    RotateLeft,
+    RotateRight,
+    Increment,
+    Decrement,
+    Negate,
+    JumpIf,
+    JumpIfCounterZero,
+    SetIf,
     Jump,
     Call,
     Push,
@@ -37,15 +53,81 @@ pub enum TokenType {
     Interrupt,
     Label,
     LabelReference,
+    Export,
     Constant,
     ConstantReference, // <- this should be translated before compilation
     Section,
+    ReadOnlySection,
+    Reservation,
+    String,
+    DataWidth,
+    SectionReference, // <- a ConstantReference that named a Section or Reservation
 }
 
-#[derive(Clone, Debug)]
+// Suffixes of conditional instructions like 🦘< with the condition
+// code that is encoded in the lower 4 bits of their opcode (p 1058).
+// Comparisons ending with u are unsigned, the others signed.
+pub const CONDITIONS: [(&str, u8); 16] = [
+    ("o", 0x0),
+    ("¬o", 0x1),
+    ("<u", 0x2),
+    ("≥u", 0x3),
+    ("=", 0x4),
+    ("≠", 0x5),
+    ("≤u", 0x6),
+    (">u", 0x7),
+    ("s", 0x8),
+    ("¬s", 0x9),
+    ("p", 0xa),
+    ("¬p", 0xb),
+    ("<", 0xc),
+    ("≥", 0xd),
+    ("≤", 0xe),
+    (">", 0xf),
+];
+
+pub fn condition_code(suffix: &str) -> Option<u8> {
+    CONDITIONS
+        .iter()
+        .find(|(condition, _)| *condition == suffix)
+        .map(|(_, code)| *code)
+}
+
+// A location in a source file. Lines and columns start at 1 and
+// columns count characters, not bytes. The column range is half-open
+// so a span of a single character has end = start + 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // Returns the smallest span on the same line that covers both
+    // self and other.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.start)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Token {
     pub t: Option<TokenType>,
     pub value: String,
+    pub span: Span,
 }
 
 impl fmt::Display for Token {
@@ -54,10 +136,32 @@ impl fmt::Display for Token {
     }
 }
 
+// Returns the smallest span covering all tokens or None if there are
+// no tokens.
+pub fn span_of<'a, I>(tokens: I) -> Option<Span>
+where
+    I: IntoIterator<Item = &'a Token>,
+{
+    tokens.into_iter().fold(None, |span, token| match span {
+        Some(span) => Some(token.span.to(&span)),
+        None => Some(token.span.clone()),
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum IntermediateCode {
     Byte(u8),
     Displacement32(String),
-
-    Padding,
+    // The address of a data section or label. It's only known once
+    // the file is laid out or linked.
+    Absolute32(String),
+    // A jump to a label. It's encoded as short_opcode with an 8 bit
+    // displacement when the label is close enough and as near_opcode
+    // with a 32 bit displacement otherwise. Jumps without
+    // near_opcode can only reach close labels.
+    Branch {
+        short_opcode: u8,
+        near_opcode: Option<Vec<u8>>,
+        label: String,
+    },
 }
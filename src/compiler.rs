@@ -15,23 +15,87 @@
 // Notes:
 // Instruction format (p 505)
 use common::*;
+use diagnostic::Diagnostic;
 use std::collections::HashSet;
-use std::{error, fmt};
-
-#[derive(Debug, Clone)]
-struct CompileError {
-    msg: String,
+use std::error;
+use tokenizer::parse_number;
+
+// A memory operand of the form offset~base~index~scale~size, e.g.
+// $8~⬇~🔵~$4~📏16 addresses the word at 8 + ⬇ + 🔵 * 4. Only the
+// offset and base are required. The scale defaults to 1 and the size
+// to the size of the other operand. The offset can also be a data
+// section or label, which can be used without a base when it's
+// followed by an index and scale, e.g. table~🔵~$4.
+struct MemoryOperand<'a> {
+    offset: &'a Token,
+    base: Option<&'a Token>,
+    index: Option<&'a Token>,
+    scale: Option<&'a Token>,
+    size: Option<&'a Token>,
 }
 
-impl fmt::Display for CompileError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.msg)
+impl<'a> MemoryOperand<'a> {
+    fn new(tokens: &'a [Token], missing: &'a Token) -> MemoryOperand<'a> {
+        let not_size = |token: &&Token| token.t != Some(TokenType::DataWidth);
+        // offset~index~scale has no base.
+        let has_base = !tokens
+            .get(2)
+            .is_some_and(|token| token.t == Some(TokenType::Value));
+        let mut rest = tokens.iter().skip(if has_base { 2 } else { 1 }).peekable();
+        let index = rest.next_if(not_size);
+        let scale = index.and_then(|_| rest.next_if(not_size));
+
+        MemoryOperand {
+            offset: tokens.first().unwrap_or(missing),
+            base: if has_base {
+                Some(tokens.get(1).unwrap_or(missing))
+            } else {
+                None
+            },
+            index,
+            scale,
+            size: rest.next(),
+        }
+    }
+
+    // Adds the token types this operand should have and its tokens,
+    // to be checked with validate_tokens.
+    fn expect(&self, expected: &mut Vec<HashSet<TokenType>>, given: &mut Vec<&'a Token>) {
+        expected.push(
+            vec![
+                TokenType::Value,
+                TokenType::SectionReference,
+                TokenType::LabelReference,
+            ]
+            .into_iter()
+            .collect(),
+        );
+        given.push(self.offset);
+        if let Some(base) = self.base {
+            expected.push(vec![TokenType::Register].into_iter().collect());
+            given.push(base);
+        }
+        if let Some(index) = self.index {
+            expected.push(vec![TokenType::Register].into_iter().collect());
+            given.push(index);
+        }
+        if let Some(scale) = self.scale {
+            expected.push(vec![TokenType::Value].into_iter().collect());
+            given.push(scale);
+        }
+        if let Some(size) = self.size {
+            expected.push(vec![TokenType::DataWidth].into_iter().collect());
+            given.push(size);
+        }
     }
-}
 
-impl error::Error for CompileError {
-    fn description(&self) -> &str {
-        self.msg.as_str()
+    // The operand without its size, e.g. $8~⬇.
+    fn without_size(&self) -> Vec<&'a Token> {
+        let mut tokens = vec![self.offset];
+        tokens.extend(self.base);
+        tokens.extend(self.index);
+        tokens.extend(self.scale);
+        tokens
     }
 }
 
@@ -46,17 +110,75 @@ trait Instruction {
     }
 
     fn get_reg_value(&self, token: &Token) -> Result<u8, Box<dyn error::Error>> {
+        match self.get_sized_reg_value(token)? {
+            (value, 32) => Ok(value),
+            (_, size) => Err(Box::new(Diagnostic::error(
+                format!(
+                    "Only 32 bit registers can be used here, {} is {} bit",
+                    token.value, size
+                ),
+                Some(token.span.clone()),
+            ))),
+        }
+    }
+
+    // The condition code of a conditional instruction, e.g. 🦘<u. The
+    // condition follows the first character.
+    fn get_condition(&self, token: &Token) -> Result<u8, Box<dyn error::Error>> {
+        let mut chars = token.value.chars();
+        chars.next();
+        condition_code(chars.as_str()).ok_or_else(|| {
+            Box::new(Diagnostic::error(
+                format!("Unknown condition in {}", token.value),
+                Some(token.span.clone()),
+            )) as Box<dyn error::Error>
+        })
+    }
+
+    // The value of a number literal, e.g. -1, 0xff or 'A'.
+    fn get_value(&self, token: &Token) -> Result<i64, Box<dyn error::Error>> {
+        match parse_number(&token.value) {
+            Ok(value) => Ok(value),
+            Err(msg) => Err(Box::new(Diagnostic::error(
+                format!("Invalid value: {}. {}", token.value, msg),
+                Some(token.span.clone()),
+            ))),
+        }
+    }
+
+    // The register number and size in bits of a register. Smaller
+    // registers have their size as suffix, e.g. ⚪8 is al.
+    fn get_sized_reg_value(&self, token: &Token) -> Result<(u8, u8), Box<dyn error::Error>> {
+        let value = token.value.as_str();
+        let (name, size) = if let Some(name) = value.strip_suffix("16") {
+            (name, 16)
+        } else if let Some(name) = value.strip_suffix('8') {
+            (name, 8)
+        } else {
+            (value, 32)
+        };
+
         // p 574
-        match token.value.as_str() {
-            "⚪" => Ok(0),  // eax
-            "🔵" => Ok(1), // ecx
-            "⚫" => Ok(2),  // edx
-            "🔴" => Ok(3), // ebx
-            "◀" => Ok(4),  // esp
-            "⬇" => Ok(5),  // ebp
-            _ => Err(Box::new(CompileError {
-                msg: format!("{} is not a valid register", token.value),
-            })),
+        let register = match name {
+            "⚪" => Some(0), // eax
+            "🔵" => Some(1), // ecx
+            "⚫" => Some(2), // edx
+            "🔴" => Some(3), // ebx
+            "◀" => Some(4),  // esp
+            "⬇" => Some(5),  // ebp
+            "🟢" => Some(6), // esi
+            "🟣" => Some(7), // edi
+            _ => None,
+        };
+
+        match register {
+            // 8 bit registers 4 to 7 are ah, ch, dh and bh, which
+            // aren't supported.
+            Some(register) if size != 8 || register < 4 => Ok((register, size)),
+            _ => Err(Box::new(Diagnostic::error(
+                format!("{} is not a valid register", token.value),
+                Some(token.span.clone()),
+            ))),
         }
     }
 
@@ -69,61 +191,263 @@ trait Instruction {
         // the instruction before and probably dropped any excess
         // tokens.
         if expected.len() != given.len() {
-            return Err(Box::new(CompileError {
-                msg: format!(
+            return Err(Box::new(Diagnostic::error(
+                format!(
                     "Grammatical error: {}, incorrect amount of tokens",
                     self.format_tokens(&given),
                 ),
-            }));
+                span_of(given.iter().cloned()),
+            )));
         }
 
         for (expected_tokens, given_token) in expected.iter().zip(given.iter()) {
             if let Some(ref given_token_t) = given_token.t {
                 if !expected_tokens.contains(given_token_t) {
-                    return Err(Box::new(CompileError {
-                        msg: format!(
+                    return Err(Box::new(Diagnostic::error(
+                        format!(
                             "Grammatical error: {}, {} should be a {:?}.",
                             self.format_tokens(&given),
                             given_token,
                             expected_tokens,
                         ),
-                    }));
+                        Some(given_token.span.clone()),
+                    )));
                 }
             } else {
-                return Err(Box::new(CompileError {
-                    msg: format!(
+                return Err(Box::new(Diagnostic::error(
+                    format!(
                         "Grammatical error: {}, expected a {:?}",
                         self.format_tokens(&given),
                         expected_tokens,
                     ),
-                }));
+                    Some(given_token.span.clone()),
+                )));
             }
         }
 
         Ok(())
     }
 
-    fn calc_modrm(&self, mod_: u8, reg_opcode: u8, rm: u8) -> u8 {
+    fn calc_modrm(&self, mod_: u8, reg_opcode: u8, rm: u8) -> Result<u8, Box<dyn error::Error>> {
         const MOD_SIZE: u32 = 2;
         const REG_OPCODE_SIZE: u32 = 3;
         const RM_SIZE: u32 = 3;
-        let msg =
-            |name, size, value| format!("{} should be {} bits but is {:#b}", name, size, value);
+        let error = |name, size, value| {
+            Err(Box::new(Diagnostic::error(
+                format!("{} should be {} bits but is {:#b}", name, size, value),
+                None,
+            )) as Box<dyn error::Error>)
+        };
 
         // modr/m p507, p513, p603
         if (mod_ >> MOD_SIZE) > 0 {
-            panic!(msg("mod", MOD_SIZE, mod_));
+            return error("mod", MOD_SIZE, mod_);
         }
 
         if (reg_opcode >> REG_OPCODE_SIZE) > 0 {
-            panic!(msg("reg_opcode", REG_OPCODE_SIZE, reg_opcode));
+            return error("reg_opcode", REG_OPCODE_SIZE, reg_opcode);
         }
 
         if (rm >> RM_SIZE) > 0 {
-            panic!(msg("rm", RM_SIZE, rm));
+            return error("rm", RM_SIZE, rm);
+        }
+
+        Ok(mod_ << 6 | reg_opcode << 3 | rm)
+    }
+
+    // The ModR/M byte, SIB byte and displacement that address memory.
+    fn encode_memory_operand(
+        &self,
+        reg_opcode: u8,
+        memory: &MemoryOperand,
+    ) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        const ESP: u8 = 4;
+        const EBP: u8 = 5;
+        // Unsigned offsets that don't fit in an i32 wrap around, just
+        // like the address calculation does. The address of a data
+        // section or label is only known once the program is linked.
+        let offset = match memory.offset.t {
+            Some(TokenType::Value) => Some(self.get_value(memory.offset)? as i32),
+            _ => None,
+        };
+        let base = match memory.base {
+            Some(base) => Some(self.get_reg_value(base)?),
+            None => None,
+        };
+        let index = match memory.index {
+            Some(index) => {
+                let value = self.get_reg_value(index)?;
+                // An index of esp in the SIB byte means there is no
+                // index.
+                if value == ESP {
+                    return Err(Box::new(Diagnostic::error(
+                        format!("{} can't be used as index", index.value),
+                        Some(index.span.clone()),
+                    )));
+                }
+                Some(value)
+            }
+            None => None,
+        };
+        // p 517
+        let scale = match memory.scale {
+            Some(scale) => self.get_value(scale)?,
+            None => 1,
+        };
+        let scale = match scale {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b10,
+            8 => 0b11,
+            _ => {
+                return Err(Box::new(Diagnostic::error(
+                    format!(
+                        "Scale {} should be 1, 2, 4 or 8",
+                        memory.scale.unwrap().value
+                    ),
+                    Some(memory.scale.unwrap().span.clone()),
+                )))
+            }
+        };
+
+        // p 513
+        // With mod 00 an r/m of ebp means a 32 bit displacement
+        // without a base, so ebp always needs a displacement. The
+        // same goes for a SIB base of ebp, which is used when there
+        // is no base.
+        let mod_ = match (base, offset) {
+            (None, _) => 0b00,
+            (Some(base), Some(0)) if base != EBP => 0b00,
+            (_, Some(offset)) if i32::from(offset as i8) == offset => 0b01,
+            _ => 0b10,
+        };
+
+        // An r/m of esp means a SIB byte follows.
+        let rm = match base {
+            Some(base) if index.is_none() && base != ESP => base,
+            _ => ESP,
+        };
+        let mut bytes = vec![IntermediateCode::Byte(
+            self.calc_modrm(mod_, reg_opcode, rm)?,
+        )];
+        if rm == ESP {
+            // The SIB byte has the same layout as the ModR/M byte.
+            bytes.push(IntermediateCode::Byte(self.calc_modrm(
+                scale,
+                index.unwrap_or(ESP),
+                base.unwrap_or(EBP),
+            )?));
+        }
+
+        match (mod_, offset) {
+            (0b01, Some(offset)) => bytes.push(IntermediateCode::Byte(offset as u8)),
+            (0b00, _) if base.is_some() => {}
+            (_, Some(offset)) => bytes.extend(
+                offset
+                    .to_le_bytes()
+                    .iter()
+                    .map(|byte| IntermediateCode::Byte(*byte)),
+            ),
+            (_, None) => bytes.push(IntermediateCode::Absolute32(memory.offset.value.clone())),
+        }
+
+        Ok(bytes)
+    }
+
+    // The opcode of an instruction operating on size bits, preceded
+    // by the operand-size prefix for 16 bit operands. byte_opcode is
+    // used for 8 bit operands.
+    fn sized_opcode(&self, size: u8, opcode: u8, byte_opcode: u8) -> Vec<IntermediateCode> {
+        match size {
+            8 => vec![IntermediateCode::Byte(byte_opcode)],
+            // p 86
+            16 => vec![IntermediateCode::Byte(0x66), IntermediateCode::Byte(opcode)],
+            _ => vec![IntermediateCode::Byte(opcode)],
+        }
+    }
+
+    // add, or, and, sub, xor and cmp share their encodings, the
+    // operation is given by extended_opcode (p 603). With
+    // short_immediate values that fit in 8 bits are sign extended
+    // instead of taking the full size.
+    fn encode_arithmetic(
+        &self,
+        extended_opcode: u8,
+        register: &Token,
+        operand: &Token,
+        short_immediate: bool,
+    ) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        let (register, size) = self.get_sized_reg_value(register)?;
+        match operand.t {
+            Some(TokenType::Value) => {
+                let value = self.get_value(operand)?;
+                let modrm = self.calc_modrm(0b11, extended_opcode, register)?;
+                if short_immediate && size != 8 && i64::from(value as i8) == value {
+                    let mut bytes = self.sized_opcode(size, 0x83, 0x83);
+                    bytes.push(IntermediateCode::Byte(modrm));
+                    bytes.push(IntermediateCode::Byte(value as u8));
+                    Ok(bytes)
+                } else {
+                    let mut bytes = self.sized_opcode(size, 0x81, 0x80);
+                    bytes.push(IntermediateCode::Byte(modrm));
+                    bytes.extend(self.encode_immediate(operand, size)?);
+                    Ok(bytes)
+                }
+            }
+            // TokenType::Register
+            _ => {
+                let (operand_register, operand_size) = self.get_sized_reg_value(operand)?;
+                if operand_size != size {
+                    return Err(self.size_mismatch(operand, operand_size, size));
+                }
+
+                let opcode = extended_opcode << 3;
+                let mut bytes = self.sized_opcode(size, opcode | 0x01, opcode);
+                bytes.push(IntermediateCode::Byte(self.calc_modrm(
+                    0b11,
+                    operand_register,
+                    register,
+                )?));
+                Ok(bytes)
+            }
+        }
+    }
+
+    // A size bits immediate.
+    fn encode_immediate(
+        &self,
+        token: &Token,
+        size: u8,
+    ) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        // Both signed and unsigned values are allowed.
+        let value = self.get_value(token)?;
+        if value < -(1 << (size - 1)) || value >= 1 << size {
+            return Err(Box::new(Diagnostic::error(
+                format!("Value {} doesn't fit in {} bits", token.value, size),
+                Some(token.span.clone()),
+            )));
         }
 
-        mod_ << 6 | reg_opcode << 3 | rm
+        Ok((value as u32).to_le_bytes()[..usize::from(size / 8)]
+            .iter()
+            .map(|b| IntermediateCode::Byte(*b))
+            .collect())
+    }
+
+    // The size in bits of a memory operand, given by its 📏 suffix.
+    fn get_memory_size(&self, size: Option<&Token>) -> Option<u8> {
+        // The tokenizer only allows 8, 16 and 32.
+        size.map(|size| size.value.parse().unwrap())
+    }
+
+    fn size_mismatch(&self, token: &Token, size: u8, expected_size: u8) -> Box<dyn error::Error> {
+        Box::new(Diagnostic::error(
+            format!(
+                "Size mismatch: {} is {} bit but should be {} bit",
+                token.value, size, expected_size
+            ),
+            Some(token.span.clone()),
+        ))
     }
 }
 
@@ -145,11 +469,12 @@ impl<'a> Instruction for InstructionMove<'a> {
                     TokenType::Value,
                     TokenType::Register,
                     TokenType::LabelReference,
+                    TokenType::SectionReference,
                 ]
                 .into_iter()
                 .collect::<HashSet<_>>(),
             ],
-            vec![&self.register, &self.operation, &self.operand],
+            vec![self.register, self.operation, self.operand],
         )
     }
 
@@ -158,42 +483,38 @@ impl<'a> Instruction for InstructionMove<'a> {
         // p 1161
         match self.operand.t {
             Some(TokenType::Value) => {
-                let mut opcode = 0xb8;
+                let (register, size) = self.get_sized_reg_value(self.register)?;
                 // register is specified in 3 LSb's
-                opcode |= self.get_reg_value(self.register)?;
-                let value = self.operand.value.parse::<u32>()?.to_le_bytes();
+                let mut bytes = self.sized_opcode(size, 0xb8 | register, 0xb0 | register);
+                bytes.extend(self.encode_immediate(self.operand, size)?);
 
-                Ok(vec![
-                    IntermediateCode::Byte(opcode),
-                    IntermediateCode::Byte(value[0]),
-                    IntermediateCode::Byte(value[1]),
-                    IntermediateCode::Byte(value[2]),
-                    IntermediateCode::Byte(value[3]),
-                ])
+                Ok(bytes)
             }
-            Some(TokenType::LabelReference) => {
+            // The address of a label or a data section.
+            Some(TokenType::LabelReference) | Some(TokenType::SectionReference) => {
                 let mut opcode = 0xb8;
                 // register is specified in 3 LSb's
                 opcode |= self.get_reg_value(self.register)?;
 
                 Ok(vec![
                     IntermediateCode::Byte(opcode),
-                    IntermediateCode::Displacement32(self.operand.value.clone()),
+                    IntermediateCode::Absolute32(self.operand.value.clone()),
                 ])
             }
             // TokenType::Register
             _ => {
-                let opcode = 0x89;
-                let modrm = self.calc_modrm(
-                    0b11,
-                    self.get_reg_value(&self.operand).unwrap(),
-                    self.get_reg_value(&self.register).unwrap(),
-                );
+                let (register, size) = self.get_sized_reg_value(self.register)?;
+                let (operand, operand_size) = self.get_sized_reg_value(self.operand)?;
+                if operand_size != size {
+                    return Err(self.size_mismatch(self.operand, operand_size, size));
+                }
 
-                Ok(vec![
-                    IntermediateCode::Byte(opcode),
-                    IntermediateCode::Byte(modrm),
-                ])
+                let mut bytes = self.sized_opcode(size, 0x89, 0x88);
+                bytes.push(IntermediateCode::Byte(
+                    self.calc_modrm(0b11, operand, register)?,
+                ));
+
+                Ok(bytes)
             }
         }
     }
@@ -202,42 +523,319 @@ impl<'a> Instruction for InstructionMove<'a> {
 struct InstructionMoveModRM<'a> {
     register: &'a Token,
     operation: &'a Token,
-    offset: &'a Token,
-    operand: &'a Token,
+    memory: MemoryOperand<'a>,
 }
 
 impl<'a> Instruction for InstructionMoveModRM<'a> {
     fn validate(&self) -> Result<(), Box<dyn error::Error>> {
-        self.validate_tokens(
-            vec![
-                vec![TokenType::Register]
-                    .into_iter()
-                    .collect::<HashSet<_>>(),
-                vec![TokenType::Move].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::Value].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::Register]
-                    .into_iter()
-                    .collect::<HashSet<_>>(),
-            ],
-            vec![&self.register, &self.operation, &self.offset, &self.operand],
-        )
+        let mut expected = vec![
+            vec![TokenType::Register]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![TokenType::Move].into_iter().collect::<HashSet<_>>(),
+        ];
+        let mut given = vec![self.register, self.operation];
+        self.memory.expect(&mut expected, &mut given);
+
+        self.validate_tokens(expected, given)
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        let (register, size) = self.get_sized_reg_value(self.register)?;
+        if let Some(memory_size) = self.get_memory_size(self.memory.size) {
+            if memory_size != size {
+                return Err(self.size_mismatch(self.register, size, memory_size));
+            }
+        }
+
+        // p 1161
+        let mut bytes = self.sized_opcode(size, 0x8b, 0x8a);
+        bytes.extend(self.encode_memory_operand(register, &self.memory)?);
+
+        Ok(bytes)
+    }
+}
+
+// Moves a smaller register or memory operand into a larger register,
+// filling the upper bits with zeroes (⬅0) or the sign bit (⬅±).
+struct InstructionMoveExtend<'a> {
+    register: &'a Token,
+    operation: &'a Token,
+    // Exactly one of operand and memory is given.
+    operand: Option<&'a Token>,
+    memory: Option<MemoryOperand<'a>>,
+}
+
+impl<'a> Instruction for InstructionMoveExtend<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        let mut expected = vec![
+            vec![TokenType::Register]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![TokenType::MoveZeroExtend, TokenType::MoveSignExtend]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        ];
+        let mut given = vec![self.register, self.operation];
+        if let Some(operand) = self.operand {
+            expected.push(vec![TokenType::Register].into_iter().collect());
+            given.push(operand);
+        }
+        if let Some(ref memory) = self.memory {
+            memory.expect(&mut expected, &mut given);
+        }
+
+        self.validate_tokens(expected, given)
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        let (register, size) = self.get_sized_reg_value(self.register)?;
+        let (operand_size, size_token) = match (self.operand, &self.memory) {
+            (Some(operand), _) => (self.get_sized_reg_value(operand)?.1, operand),
+            (None, Some(memory)) => match memory.size {
+                Some(size_token) => (self.get_memory_size(memory.size).unwrap(), size_token),
+                None => {
+                    return Err(Box::new(Diagnostic::error(
+                        format!(
+                            "{} needs the size of the memory operand, e.g. {}~📏8",
+                            self.operation.value,
+                            memory
+                                .without_size()
+                                .iter()
+                                .map(|token| token.value.as_str())
+                                .collect::<Vec<_>>()
+                                .join("~")
+                        ),
+                        span_of(memory.without_size()),
+                    )))
+                }
+            },
+            (None, None) => unreachable!(),
+        };
+        if operand_size >= size {
+            return Err(Box::new(Diagnostic::error(
+                format!(
+                    "{} can only move into a larger register, {} is {} bit and {} is {} bit",
+                    self.operation.value, self.register.value, size, size_token.value, operand_size
+                ),
+                Some(size_token.span.clone()),
+            )));
+        }
+
+        // movzx p 1205, movsx p 1201
+        let opcode = match (&self.operation.t, operand_size) {
+            (Some(TokenType::MoveZeroExtend), 8) => 0xb6,
+            (Some(TokenType::MoveZeroExtend), _) => 0xb7,
+            (_, 8) => 0xbe,
+            _ => 0xbf,
+        };
+        let mut bytes = self.sized_opcode(size, 0x0f, 0x0f);
+        bytes.push(IntermediateCode::Byte(opcode));
+        match (self.operand, &self.memory) {
+            (Some(operand), _) => {
+                let operand = self.get_sized_reg_value(operand)?.0;
+                bytes.push(IntermediateCode::Byte(
+                    self.calc_modrm(0b11, register, operand)?,
+                ));
+            }
+            (None, Some(memory)) => {
+                bytes.extend(self.encode_memory_operand(register, memory)?);
+            }
+            (None, None) => unreachable!(),
+        }
+
+        Ok(bytes)
+    }
+}
+
+// cmovcc, moves only if the condition holds.
+struct InstructionMoveIf<'a> {
+    register: &'a Token,
+    operation: &'a Token,
+    // Exactly one of operand and memory is given.
+    operand: Option<&'a Token>,
+    memory: Option<MemoryOperand<'a>>,
+}
+
+impl<'a> Instruction for InstructionMoveIf<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        let mut expected = vec![
+            vec![TokenType::Register]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![TokenType::MoveIf].into_iter().collect::<HashSet<_>>(),
+        ];
+        let mut given = vec![self.register, self.operation];
+        if let Some(operand) = self.operand {
+            expected.push(vec![TokenType::Register].into_iter().collect());
+            given.push(operand);
+        }
+        if let Some(ref memory) = self.memory {
+            memory.expect(&mut expected, &mut given);
+        }
+
+        self.validate_tokens(expected, given)
     }
 
     fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
         self.validate()?;
 
-        let modrm = self.calc_modrm(
-            0b01,
-            self.get_reg_value(self.register).unwrap(),
-            self.get_reg_value(self.operand).unwrap(),
+        // p 710
+        let register = self.get_reg_value(self.register)?;
+        let mut bytes = vec![
+            IntermediateCode::Byte(0x0f),
+            IntermediateCode::Byte(0x40 | self.get_condition(self.operation)?),
+        ];
+        match (self.operand, &self.memory) {
+            (Some(operand), _) => {
+                let operand = self.get_reg_value(operand)?;
+                bytes.push(IntermediateCode::Byte(
+                    self.calc_modrm(0b11, register, operand)?,
+                ));
+            }
+            (None, Some(memory)) => {
+                if let (Some(size), Some(size_token)) =
+                    (self.get_memory_size(memory.size), memory.size)
+                {
+                    if size != 32 {
+                        return Err(Box::new(Diagnostic::error(
+                            format!(
+                                "{} can only move 32 bit values, the memory operand is {} bit",
+                                self.operation.value, size
+                            ),
+                            Some(size_token.span.clone()),
+                        )));
+                    }
+                }
+                bytes.extend(self.encode_memory_operand(register, memory)?);
+            }
+            (None, None) => unreachable!(),
+        }
+
+        Ok(bytes)
+    }
+}
+
+struct InstructionStore<'a> {
+    destination: &'a Token,
+    // 📏 suffix of the destination
+    size: Option<&'a Token>,
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionStore<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        let mut expected = vec![vec![TokenType::SectionReference]
+            .into_iter()
+            .collect::<HashSet<_>>()];
+        let mut given = vec![self.destination];
+        if let Some(size) = self.size {
+            expected.push(vec![TokenType::DataWidth].into_iter().collect());
+            given.push(size);
+        }
+        expected.push(vec![TokenType::Move].into_iter().collect());
+        expected.push(
+            vec![TokenType::Value, TokenType::Register]
+                .into_iter()
+                .collect(),
         );
+        given.push(self.operation);
+        given.push(self.operand);
+
+        self.validate_tokens(expected, given)
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
 
         // p 1161
-        Ok(vec![
-            IntermediateCode::Byte(0x8b),
-            IntermediateCode::Byte(modrm),
-            IntermediateCode::Byte(self.offset.value.parse::<i8>()? as u8), // TODO support 32 bit offsets
-        ])
+        // mod 00 with r/m 101 means a 32 bit address follows.
+        let rm = 0b101;
+        let memory_size = self.get_memory_size(self.size);
+        match self.operand.t {
+            Some(TokenType::Value) => {
+                let size = memory_size.unwrap_or(32);
+                let mut bytes = self.sized_opcode(size, 0xc7, 0xc6);
+                bytes.push(IntermediateCode::Byte(self.calc_modrm(0b00, 0, rm)?));
+                bytes.push(IntermediateCode::Absolute32(self.destination.value.clone()));
+                bytes.extend(self.encode_immediate(self.operand, size)?);
+
+                Ok(bytes)
+            }
+            // TokenType::Register
+            _ => {
+                let (operand, size) = self.get_sized_reg_value(self.operand)?;
+                if let Some(memory_size) = memory_size {
+                    if memory_size != size {
+                        return Err(self.size_mismatch(self.operand, size, memory_size));
+                    }
+                }
+
+                let mut bytes = self.sized_opcode(size, 0x89, 0x88);
+                bytes.push(IntermediateCode::Byte(self.calc_modrm(0b00, operand, rm)?));
+                bytes.push(IntermediateCode::Absolute32(self.destination.value.clone()));
+
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+struct InstructionStoreModRM<'a> {
+    memory: MemoryOperand<'a>,
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionStoreModRM<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        let mut expected = vec![];
+        let mut given = vec![];
+        self.memory.expect(&mut expected, &mut given);
+        expected.push(vec![TokenType::Move].into_iter().collect());
+        expected.push(
+            vec![TokenType::Value, TokenType::Register]
+                .into_iter()
+                .collect(),
+        );
+        given.push(self.operation);
+        given.push(self.operand);
+
+        self.validate_tokens(expected, given)
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // p 1161
+        let memory_size = self.get_memory_size(self.memory.size);
+        match self.operand.t {
+            Some(TokenType::Value) => {
+                let size = memory_size.unwrap_or(32);
+                let mut bytes = self.sized_opcode(size, 0xc7, 0xc6);
+                bytes.extend(self.encode_memory_operand(0, &self.memory)?);
+                bytes.extend(self.encode_immediate(self.operand, size)?);
+                Ok(bytes)
+            }
+            // TokenType::Register
+            _ => {
+                let (operand, size) = self.get_sized_reg_value(self.operand)?;
+                if let Some(memory_size) = memory_size {
+                    if memory_size != size {
+                        return Err(self.size_mismatch(self.operand, size, memory_size));
+                    }
+                }
+
+                let mut bytes = self.sized_opcode(size, 0x89, 0x88);
+                bytes.extend(self.encode_memory_operand(operand, &self.memory)?);
+                Ok(bytes)
+            }
+        }
     }
 }
 
@@ -261,7 +859,7 @@ impl<'a> Instruction for InstructionAddSubtract<'a> {
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.register, &self.operation, &self.operand],
+            vec![self.register, self.operation, self.operand],
         )
     }
 
@@ -269,45 +867,12 @@ impl<'a> Instruction for InstructionAddSubtract<'a> {
         self.validate()?;
 
         // p603
-        match self.operand.t {
-            Some(TokenType::Value) => {
-                let value = self.operand.value.parse::<u32>()?.to_le_bytes();
-                let opcode = if let Some(TokenType::Add) = self.operation.t {
-                    0x0
-                } else {
-                    0x5
-                };
-                let modrm =
-                    self.calc_modrm(0b11, opcode, self.get_reg_value(&self.register).unwrap());
-
-                Ok(vec![
-                    IntermediateCode::Byte(0x81), // 32 bit adds
-                    IntermediateCode::Byte(modrm),
-                    IntermediateCode::Byte(value[0]),
-                    IntermediateCode::Byte(value[1]),
-                    IntermediateCode::Byte(value[2]),
-                    IntermediateCode::Byte(value[3]),
-                ])
-            }
-            // TokenType::Register
-            _ => {
-                let opcode = if let Some(TokenType::Add) = self.operation.t {
-                    0x01
-                } else {
-                    0x29
-                };
-                let modrm = self.calc_modrm(
-                    0b11,
-                    self.get_reg_value(&self.operand).unwrap(),
-                    self.get_reg_value(&self.register).unwrap(),
-                );
-
-                Ok(vec![
-                    IntermediateCode::Byte(opcode),
-                    IntermediateCode::Byte(modrm),
-                ])
-            }
-        }
+        let extended_opcode = if let Some(TokenType::Add) = self.operation.t {
+            0
+        } else {
+            5
+        };
+        self.encode_arithmetic(extended_opcode, self.register, self.operand, false)
     }
 }
 
@@ -331,7 +896,7 @@ impl<'a> Instruction for InstructionMultiply<'a> {
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.register, &self.operation, &self.operand],
+            vec![self.register, self.operation, self.operand],
         )
     }
 
@@ -344,11 +909,10 @@ impl<'a> Instruction for InstructionMultiply<'a> {
                 let opcode = 0x69;
                 let modrm_destination = self.calc_modrm(
                     0b11,
-                    self.get_reg_value(&self.register).unwrap(),
-                    self.get_reg_value(&self.register).unwrap(),
-                );
-                // TODO change to i32 when signed integer support is added
-                let value = self.operand.value.parse::<u32>()?.to_le_bytes();
+                    self.get_reg_value(self.register)?,
+                    self.get_reg_value(self.register)?,
+                )?;
+                let value = (self.get_value(self.operand)? as u32).to_le_bytes();
 
                 Ok(vec![
                     IntermediateCode::Byte(opcode),
@@ -365,9 +929,9 @@ impl<'a> Instruction for InstructionMultiply<'a> {
                 let operand1 = 0xaf;
                 let operand2 = self.calc_modrm(
                     0b11,
-                    self.get_reg_value(&self.register).unwrap(),
-                    self.get_reg_value(&self.operand).unwrap(),
-                );
+                    self.get_reg_value(self.register)?,
+                    self.get_reg_value(self.operand)?,
+                )?;
                 Ok(vec![
                     IntermediateCode::Byte(opcode),
                     IntermediateCode::Byte(operand1),
@@ -378,32 +942,359 @@ impl<'a> Instruction for InstructionMultiply<'a> {
     }
 }
 
-struct InstructionJump<'a> {
+struct InstructionDivide<'a> {
+    register: &'a Token,
     operation: &'a Token,
     operand: &'a Token,
 }
 
-impl<'a> Instruction for InstructionJump<'a> {
+impl<'a> Instruction for InstructionDivide<'a> {
     fn validate(&self) -> Result<(), Box<dyn error::Error>> {
         self.validate_tokens(
             vec![
-                vec![TokenType::Jump].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::LabelReference]
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                vec![
+                    TokenType::Divide,
+                    TokenType::DivideUnsigned,
+                    TokenType::Remainder,
+                    TokenType::RemainderUnsigned,
+                ]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+                vec![TokenType::Register]
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.register, self.operation, self.operand],
+        )?;
+
+        // div and idiv divide edx:eax and put the quotient in eax and
+        // the remainder in edx.
+        if self.get_reg_value(self.register)? != 0 {
+            return Err(Box::new(Diagnostic::error(
+                format!(
+                    "{} can only be used with ⚪ on the left",
+                    self.operation.value
+                ),
+                Some(self.register.span.clone()),
+            )));
+        }
+
+        // edx is overwritten before dividing.
+        if self.get_reg_value(self.operand)? == 2 {
+            return Err(Box::new(Diagnostic::error(
+                format!("{} can't be used to divide by ⚫", self.operation.value),
+                Some(self.operand.span.clone()),
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        let signed = matches!(
+            self.operation.t,
+            Some(TokenType::Divide) | Some(TokenType::Remainder)
+        );
+        let remainder = matches!(
+            self.operation.t,
+            Some(TokenType::Remainder) | Some(TokenType::RemainderUnsigned)
+        );
+
+        let mut bytes = if signed {
+            // cdq, p 731
+            vec![IntermediateCode::Byte(0x99)]
+        } else {
+            // xor edx, edx
+            vec![IntermediateCode::Byte(0x31), IntermediateCode::Byte(0xd2)]
+        };
+
+        // idiv p 1014, div p 771
+        let extended_opcode = if signed { 7 } else { 6 };
+        let modrm = self.calc_modrm(0b11, extended_opcode, self.get_reg_value(self.operand)?)?;
+        bytes.push(IntermediateCode::Byte(0xf7));
+        bytes.push(IntermediateCode::Byte(modrm));
+
+        if remainder {
+            // mov eax, edx
+            let modrm = self.calc_modrm(0b11, 2, 0)?;
+            bytes.push(IntermediateCode::Byte(0x89));
+            bytes.push(IntermediateCode::Byte(modrm));
+        }
+
+        Ok(bytes)
+    }
+}
+
+struct InstructionLogic<'a> {
+    register: &'a Token,
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionLogic<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                vec![TokenType::And, TokenType::Or, TokenType::Xor]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                vec![TokenType::Value, TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.register, self.operation, self.operand],
+        )
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // and p 665, or p 1219, xor p 1933
+        let extended_opcode = match self.operation.t {
+            Some(TokenType::And) => 4,
+            Some(TokenType::Or) => 1,
+            _ => 6,
+        };
+        self.encode_arithmetic(extended_opcode, self.register, self.operand, false)
+    }
+}
+
+struct InstructionNot<'a> {
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionNot<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Not].into_iter().collect::<HashSet<_>>(),
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.operation, self.operand],
+        )
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // p 1217
+        let (operand, size) = self.get_sized_reg_value(self.operand)?;
+        let mut bytes = self.sized_opcode(size, 0xf7, 0xf6);
+        bytes.push(IntermediateCode::Byte(self.calc_modrm(0b11, 2, operand)?));
+        Ok(bytes)
+    }
+}
+
+struct InstructionShift<'a> {
+    register: &'a Token,
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionShift<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                vec![
+                    TokenType::ShiftLeft,
+                    TokenType::ShiftRight,
+                    TokenType::ShiftRightArithmetic,
+                    TokenType::RotateLeft,
+                    TokenType::RotateRight,
+                ]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+                vec![TokenType::Value, TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.register, self.operation, self.operand],
+        )?;
+
+        // The count can only be given in cl.
+        if self.operand.t == Some(TokenType::Register) && self.get_reg_value(self.operand)? != 1 {
+            return Err(Box::new(Diagnostic::error(
+                format!(
+                    "{} can only shift by a count in 🔵, not {}",
+                    self.operation.value, self.operand.value
+                ),
+                Some(self.operand.span.clone()),
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // rcl/rcr/rol/ror p 1637, sal/sar/shl/shr p 1718
+        let extended_opcode = match self.operation.t {
+            Some(TokenType::RotateLeft) => 0,
+            Some(TokenType::RotateRight) => 1,
+            Some(TokenType::ShiftLeft) => 4,
+            Some(TokenType::ShiftRight) => 5,
+            _ => 7,
+        };
+        let (register, size) = self.get_sized_reg_value(self.register)?;
+        let modrm = IntermediateCode::Byte(self.calc_modrm(0b11, extended_opcode, register)?);
+
+        match self.operand.t {
+            Some(TokenType::Value) => {
+                let count = self.get_value(self.operand)?;
+                if count < 0 {
+                    return Err(Box::new(Diagnostic::error(
+                        format!("Shift count {} can't be negative", count),
+                        Some(self.operand.span.clone()),
+                    )));
+                }
+                // Only the lowest 5 bits of the count are used.
+                if count > 31 {
+                    return Err(Box::new(Diagnostic::error(
+                        format!("Shift count {} should be at most 31", count),
+                        Some(self.operand.span.clone()),
+                    )));
+                }
+
+                if count == 1 {
+                    let mut bytes = self.sized_opcode(size, 0xd1, 0xd0);
+                    bytes.push(modrm);
+                    Ok(bytes)
+                } else {
+                    let mut bytes = self.sized_opcode(size, 0xc1, 0xc0);
+                    bytes.push(modrm);
+                    bytes.push(IntermediateCode::Byte(count as u8));
+                    Ok(bytes)
+                }
+            }
+            // TokenType::Register
+            _ => {
+                let mut bytes = self.sized_opcode(size, 0xd3, 0xd2);
+                bytes.push(modrm);
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+struct InstructionIncrementDecrement<'a> {
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionIncrementDecrement<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Increment, TokenType::Decrement]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.operation, self.operand],
+        )
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // inc p 1025, dec p 773
+        let increment = self.operation.t == Some(TokenType::Increment);
+        let (operand, size) = self.get_sized_reg_value(self.operand)?;
+        if size == 8 {
+            // There are no one byte forms for 8 bit registers.
+            let extended_opcode = if increment { 0 } else { 1 };
+            return Ok(vec![
+                IntermediateCode::Byte(0xfe),
+                IntermediateCode::Byte(self.calc_modrm(0b11, extended_opcode, operand)?),
+            ]);
+        }
+
+        let opcode = if increment { 0x40 } else { 0x48 };
+        Ok(self.sized_opcode(size, opcode + operand, opcode + operand))
+    }
+}
+
+struct InstructionNegate<'a> {
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionNegate<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Negate].into_iter().collect::<HashSet<_>>(),
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.operation, self.operand],
+        )
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // p 1212
+        let (operand, size) = self.get_sized_reg_value(self.operand)?;
+        let mut bytes = self.sized_opcode(size, 0xf7, 0xf6);
+        bytes.push(IntermediateCode::Byte(self.calc_modrm(0b11, 3, operand)?));
+        Ok(bytes)
+    }
+}
+
+struct InstructionJump<'a> {
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionJump<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::Jump].into_iter().collect::<HashSet<_>>(),
+                vec![TokenType::LabelReference, TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.operation, self.operand],
         )
     }
 
     fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
         self.validate()?;
         // p 1063
+        if self.operand.t == Some(TokenType::Register) {
+            let register = self.get_reg_value(self.operand)?;
+            return Ok(vec![
+                IntermediateCode::Byte(0xff),
+                IntermediateCode::Byte(self.calc_modrm(0b11, 4, register)?),
+            ]);
+        }
+
         // p 87 specifying an offset
-        Ok(vec![
-            IntermediateCode::Byte(0xe9),
-            IntermediateCode::Displacement32(self.operand.value.clone()),
-        ])
+        Ok(vec![IntermediateCode::Branch {
+            short_opcode: 0xeb,
+            near_opcode: Some(vec![0xe9]),
+            label: self.operand.value.clone(),
+        }])
     }
 }
 
@@ -417,17 +1308,25 @@ impl<'a> Instruction for InstructionCall<'a> {
         self.validate_tokens(
             vec![
                 vec![TokenType::Call].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::LabelReference]
+                vec![TokenType::LabelReference, TokenType::Register]
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.operation, self.operand],
         )
     }
 
     fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
         self.validate()?;
         // p 694
+        if self.operand.t == Some(TokenType::Register) {
+            let register = self.get_reg_value(self.operand)?;
+            return Ok(vec![
+                IntermediateCode::Byte(0xff),
+                IntermediateCode::Byte(self.calc_modrm(0b11, 2, register)?),
+            ]);
+        }
+
         // p 87 specifying an offset
         Ok(vec![
             IntermediateCode::Byte(0xe8),
@@ -436,6 +1335,39 @@ impl<'a> Instruction for InstructionCall<'a> {
     }
 }
 
+// A jump or call to the address stored in memory.
+struct InstructionJumpModRM<'a> {
+    operation: &'a Token,
+    memory: MemoryOperand<'a>,
+}
+
+impl<'a> Instruction for InstructionJumpModRM<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        let mut expected = vec![vec![TokenType::Jump, TokenType::Call]
+            .into_iter()
+            .collect::<HashSet<_>>()];
+        let mut given = vec![self.operation];
+        self.memory.expect(&mut expected, &mut given);
+
+        self.validate_tokens(expected, given)
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // p 694, p 1063
+        let opcode = 0xff;
+        let extended_opcode = match self.operation.t {
+            Some(TokenType::Call) => 2,
+            _ => 4,
+        };
+
+        let mut bytes = vec![IntermediateCode::Byte(opcode)];
+        bytes.extend(self.encode_memory_operand(extended_opcode, &self.memory)?);
+        Ok(bytes)
+    }
+}
+
 struct InstructionReturn<'a> {
     operation: &'a Token,
 }
@@ -444,7 +1376,7 @@ impl<'a> Instruction for InstructionReturn<'a> {
     fn validate(&self) -> Result<(), Box<dyn error::Error>> {
         self.validate_tokens(
             vec![vec![TokenType::Return].into_iter().collect::<HashSet<_>>()],
-            vec![&self.operation],
+            vec![self.operation],
         )
     }
 
@@ -469,16 +1401,24 @@ impl<'a> Instruction for InstructionInterrupt<'a> {
                     .collect::<HashSet<_>>(),
                 vec![TokenType::Value].into_iter().collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.operation, self.operand],
         )
     }
 
     fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
         self.validate()?;
         // p 1031
+        let vector = self.get_value(self.operand)?;
+        if !(0..=255).contains(&vector) {
+            return Err(Box::new(Diagnostic::error(
+                format!("Interrupt {} should be from 0 to 255", self.operand.value),
+                Some(self.operand.span.clone()),
+            )));
+        }
+
         Ok(vec![
             IntermediateCode::Byte(0xcd),
-            IntermediateCode::Byte(self.operand.value.parse::<u8>()?),
+            IntermediateCode::Byte(vector as u8),
         ])
     }
 }
@@ -493,11 +1433,16 @@ impl<'a> Instruction for InstructionPush<'a> {
         self.validate_tokens(
             vec![
                 vec![TokenType::Push].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::Value, TokenType::Register]
-                    .into_iter()
-                    .collect::<HashSet<_>>(),
+                vec![
+                    TokenType::Value,
+                    TokenType::Register,
+                    TokenType::LabelReference,
+                    TokenType::SectionReference,
+                ]
+                .into_iter()
+                .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.operation, self.operand],
         )
     }
 
@@ -507,7 +1452,7 @@ impl<'a> Instruction for InstructionPush<'a> {
         // p 1633
         match self.operand.t {
             Some(TokenType::Value) => {
-                let value = self.operand.value.parse::<u32>()?.to_le_bytes();
+                let value = (self.get_value(self.operand)? as u32).to_le_bytes();
                 Ok(vec![
                     IntermediateCode::Byte(0x68),
                     IntermediateCode::Byte(value[0]),
@@ -516,11 +1461,15 @@ impl<'a> Instruction for InstructionPush<'a> {
                     IntermediateCode::Byte(value[3]),
                 ])
             }
+            Some(TokenType::LabelReference) | Some(TokenType::SectionReference) => Ok(vec![
+                IntermediateCode::Byte(0x68),
+                IntermediateCode::Absolute32(self.operand.value.clone()),
+            ]),
             // TokenType::Register
             _ => {
                 let opcode = 0x50;
                 Ok(vec![IntermediateCode::Byte(
-                    opcode + self.get_reg_value(&self.operand).unwrap(),
+                    opcode + self.get_reg_value(self.operand)?,
                 )])
             }
         }
@@ -529,43 +1478,28 @@ impl<'a> Instruction for InstructionPush<'a> {
 
 struct InstructionPushModRM<'a> {
     operation: &'a Token,
-    offset: &'a Token,
-    register: &'a Token,
+    memory: MemoryOperand<'a>,
 }
 
 impl<'a> Instruction for InstructionPushModRM<'a> {
     fn validate(&self) -> Result<(), Box<dyn error::Error>> {
-        self.validate_tokens(
-            vec![
-                vec![TokenType::Push].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::Value].into_iter().collect::<HashSet<_>>(),
-                vec![TokenType::Register]
-                    .into_iter()
-                    .collect::<HashSet<_>>(),
-            ],
-            vec![&self.operation, &self.offset, &self.register],
-        )
+        let mut expected = vec![vec![TokenType::Push].into_iter().collect::<HashSet<_>>()];
+        let mut given = vec![self.operation];
+        self.memory.expect(&mut expected, &mut given);
+
+        self.validate_tokens(expected, given)
     }
 
     fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
         self.validate()?;
 
         let opcode = 0xff;
-
         let extended_opcode = 6;
-        let mod_ = 0b01;
-        let modrm = self.calc_modrm(
-            mod_,
-            extended_opcode,
-            self.get_reg_value(&self.register).unwrap(),
-        );
 
         // p 1633
-        Ok(vec![
-            IntermediateCode::Byte(opcode),
-            IntermediateCode::Byte(modrm),
-            IntermediateCode::Byte(self.offset.value.parse::<i8>()? as u8), // TODO support 32 bit offsets
-        ])
+        let mut bytes = vec![IntermediateCode::Byte(opcode)];
+        bytes.extend(self.encode_memory_operand(extended_opcode, &self.memory)?);
+        Ok(bytes)
     }
 }
 
@@ -583,7 +1517,7 @@ impl<'a> Instruction for InstructionPop<'a> {
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.operation, self.operand],
         )
     }
 
@@ -591,7 +1525,7 @@ impl<'a> Instruction for InstructionPop<'a> {
         self.validate()?;
 
         // p 1633
-        let opcode = 0x58 | self.get_reg_value(&self.operand).unwrap();
+        let opcode = 0x58 | self.get_reg_value(self.operand)?;
         Ok(vec![IntermediateCode::Byte(opcode)])
     }
 }
@@ -614,7 +1548,7 @@ impl<'a> Instruction for InstructionCompare<'a> {
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.left_operand, &self.right_operand],
+            vec![self.operation, self.left_operand, self.right_operand],
         )
     }
 
@@ -630,33 +1564,10 @@ impl<'a> Instruction for InstructionCompare<'a> {
         // In a conventional assembly language with AT&T syntax this
         // would make jge jump, because there the second argument is
         // compared to the first.
-        match self.right_operand.t {
-            Some(TokenType::Register) => {
-                let opcode = 0x39;
-                let modrm = self.calc_modrm(
-                    0b11,
-                    self.get_reg_value(&self.right_operand).unwrap(),
-                    self.get_reg_value(&self.left_operand).unwrap(),
-                );
-
-                Ok(vec![
-                    IntermediateCode::Byte(opcode),
-                    IntermediateCode::Byte(modrm),
-                ])
-            }
-            // Some(TokenType::Value)
-            _ => {
-                let opcode = 0x83;
-                let modrm =
-                    self.calc_modrm(0b11, 0x07, self.get_reg_value(&self.left_operand).unwrap());
-
-                Ok(vec![
-                    IntermediateCode::Byte(opcode),
-                    IntermediateCode::Byte(modrm),
-                    IntermediateCode::Byte(self.right_operand.value.parse::<i8>()? as u8), // TODO support 32 bit
-                ])
-            }
-        }
+        //
+        // Use the shorter encoding with a sign-extended 8 bit
+        // immediate when possible.
+        self.encode_arithmetic(7, self.left_operand, self.right_operand, true)
     }
 }
 
@@ -669,21 +1580,14 @@ impl<'a> Instruction for InstructionJumpIf<'a> {
     fn validate(&self) -> Result<(), Box<dyn error::Error>> {
         self.validate_tokens(
             vec![
-                vec![
-                    TokenType::JumpIfEqual,
-                    TokenType::JumpIfNotEqual,
-                    TokenType::JumpIfLess,
-                    TokenType::JumpIfLessEqual,
-                    TokenType::JumpIfGreater,
-                    TokenType::JumpIfGreaterEqual,
-                ]
-                .into_iter()
-                .collect::<HashSet<_>>(),
+                vec![TokenType::JumpIf, TokenType::JumpIfCounterZero]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
                 vec![TokenType::LabelReference]
                     .into_iter()
                     .collect::<HashSet<_>>(),
             ],
-            vec![&self.operation, &self.operand],
+            vec![self.operation, self.operand],
         )
     }
 
@@ -691,48 +1595,99 @@ impl<'a> Instruction for InstructionJumpIf<'a> {
         self.validate()?;
 
         // p 1058
-        // Only supports near (32 bit) jumps
-        let opcode1 = 0x0f;
-        let opcode2 = match self.operation.t {
-            Some(TokenType::JumpIfEqual) => 0x84,
-            Some(TokenType::JumpIfNotEqual) => 0x85,
-            Some(TokenType::JumpIfLess) => 0x8c,
-            Some(TokenType::JumpIfLessEqual) => 0x8e,
-            Some(TokenType::JumpIfGreater) => 0x8f,
-            Some(TokenType::JumpIfGreaterEqual) => 0x8d,
-            _ => panic!(
-                "Attempting to compile invalid InstructionJumpIf: {:?}.",
-                self.operation.t
-            ),
-        };
-        Ok(vec![
-            IntermediateCode::Byte(opcode1),
-            IntermediateCode::Byte(opcode2),
-            IntermediateCode::Displacement32(self.operand.value.clone()),
-        ])
+        // jecxz only exists with an 8 bit displacement.
+        if self.operation.t == Some(TokenType::JumpIfCounterZero) {
+            return Ok(vec![IntermediateCode::Branch {
+                short_opcode: 0xe3,
+                near_opcode: None,
+                label: self.operand.value.clone(),
+            }]);
+        }
+
+        // The condition is encoded in the lower 4 bits of both the
+        // short (0x7x) and near (0x0f 0x8x) opcodes.
+        let condition = self.get_condition(self.operation)?;
+        Ok(vec![IntermediateCode::Branch {
+            short_opcode: 0x70 | condition,
+            near_opcode: Some(vec![0x0f, 0x80 | condition]),
+            label: self.operand.value.clone(),
+        }])
     }
 }
 
-#[cfg(test)]
-mod test_instructions {
-    use super::*;
-
-    #[test]
-    #[should_panic(expected = "mod should be 2 bits but is 0b111")]
-    fn test_calc_modrm_panic() {
+// setcc, sets an 8 bit register to 1 if the condition holds and to 0
+// otherwise.
+struct InstructionSetIf<'a> {
+    operation: &'a Token,
+    operand: &'a Token,
+}
+
+impl<'a> Instruction for InstructionSetIf<'a> {
+    fn validate(&self) -> Result<(), Box<dyn error::Error>> {
+        self.validate_tokens(
+            vec![
+                vec![TokenType::SetIf].into_iter().collect::<HashSet<_>>(),
+                vec![TokenType::Register]
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+            ],
+            vec![self.operation, self.operand],
+        )
+    }
+
+    fn compile(&self) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+        self.validate()?;
+
+        // p 1698
+        let (register, size) = self.get_sized_reg_value(self.operand)?;
+        if size != 8 {
+            return Err(self.size_mismatch(self.operand, size, 8));
+        }
+
+        Ok(vec![
+            IntermediateCode::Byte(0x0f),
+            IntermediateCode::Byte(0x90 | self.get_condition(self.operation)?),
+            IntermediateCode::Byte(self.calc_modrm(0b11, 0, register)?),
+        ])
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod test_instructions {
+    use super::*;
+
+    fn memory<'a>(offset: &'a Token, base: &'a Token) -> MemoryOperand<'a> {
+        MemoryOperand {
+            offset,
+            base: Some(base),
+            index: None,
+            scale: None,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn test_calc_modrm_error() {
         let i = InstructionJump {
             operation: &Token {
                 t: None,
                 value: "".to_string(),
+                ..Default::default()
             },
 
             operand: &Token {
                 t: None,
                 value: "".to_string(),
+                ..Default::default()
             },
         };
 
-        i.calc_modrm(0b111, 0, 0);
+        let error = i.calc_modrm(0b111, 0, 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error: mod should be 2 bits but is 0b111"
+        );
     }
 
     #[test]
@@ -741,15 +1696,17 @@ mod test_instructions {
             operation: &Token {
                 t: None,
                 value: "".to_string(),
+                ..Default::default()
             },
 
             operand: &Token {
                 t: None,
                 value: "".to_string(),
+                ..Default::default()
             },
         };
 
-        assert_eq!(i.calc_modrm(0b11, 0b011, 0b100), 0b11011100);
+        assert_eq!(i.calc_modrm(0b11, 0b011, 0b100).unwrap(), 0b11011100);
     }
 
     fn vec_compare(va: &[IntermediateCode], vb: &[IntermediateCode]) -> bool {
@@ -765,14 +1722,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚫".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "1".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -798,14 +1758,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "0".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -831,14 +1794,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "4294967294".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -864,14 +1830,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔵".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "◀".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -886,29 +1855,65 @@ mod test_instructions {
         ));
     }
 
+    #[test]
+    fn test_move_section() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::SectionReference),
+            value: "numbers".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionMove {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0xb9),
+                IntermediateCode::Absolute32("numbers".to_string()),
+            ],
+            &bytes
+        ));
+    }
+
     #[test]
     fn test_move_modrm1() {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "⬇".to_string(),
+            ..Default::default()
         };
         let offset = Token {
             t: Some(TokenType::Value),
             value: "8".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMoveModRM {
             register: &register,
             operation: &operation,
-            operand: &operand,
-            offset: &offset,
+            memory: memory(&offset, &operand),
         };
 
         let bytes = instruction.compile().unwrap();
@@ -927,14 +1932,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚫".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Add),
             value: "➕".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "4294967294".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionAddSubtract {
             register: &register,
@@ -961,14 +1969,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚫".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Add),
             value: "➕".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "7".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionAddSubtract {
             register: &register,
@@ -995,14 +2006,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Subtract),
             value: "➖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "7".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionAddSubtract {
             register: &register,
@@ -1029,14 +2043,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Add),
             value: "➕".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "⚫".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionAddSubtract {
             register: &register,
@@ -1056,14 +2073,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Subtract),
             value: "➖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionAddSubtract {
             register: &register,
@@ -1083,14 +2103,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Multiply),
             value: "✖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "0".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMultiply {
             register: &register,
@@ -1117,14 +2140,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Multiply),
             value: "✖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "3223133".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMultiply {
             register: &register,
@@ -1151,14 +2177,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Multiply),
             value: "✖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "⚫".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMultiply {
             register: &register,
@@ -1182,14 +2211,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Multiply),
             value: "✖".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMultiply {
             register: &register,
@@ -1213,10 +2245,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Jump),
             value: "🦘".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::LabelReference),
             value: "test_label".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionJump {
             operation: &operation,
@@ -1225,10 +2259,11 @@ mod test_instructions {
 
         let bytes = instruction.compile().unwrap();
         assert!(vec_compare(
-            &[
-                IntermediateCode::Byte(0xe9),
-                IntermediateCode::Displacement32("test_label".to_string())
-            ],
+            &[IntermediateCode::Branch {
+                short_opcode: 0xeb,
+                near_opcode: Some(vec![0xe9]),
+                label: "test_label".to_string(),
+            }],
             &bytes
         ));
     }
@@ -1236,38 +2271,161 @@ mod test_instructions {
     #[test]
     fn test_jump_if() {
         let operation = Token {
-            t: Some(TokenType::JumpIfEqual),
+            t: Some(TokenType::JumpIf),
             value: "🦘=".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::LabelReference),
+            value: "test_label".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionJumpIf {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Branch {
+                short_opcode: 0x74,
+                near_opcode: Some(vec![0x0f, 0x84]),
+                label: "test_label".to_string(),
+            }],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_jump_if_unsigned() {
+        let operation = Token {
+            t: Some(TokenType::JumpIf),
+            value: "🦘≤u".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::LabelReference),
             value: "test_label".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionJumpIf {
             operation: &operation,
             operand: &operand,
         };
 
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Branch {
+                short_opcode: 0x76,
+                near_opcode: Some(vec![0x0f, 0x86]),
+                label: "test_label".to_string(),
+            }],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_set_if() {
+        let operation = Token {
+            t: Some(TokenType::SetIf),
+            value: "✔≠".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔵8".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionSetIf {
+            operation: &operation,
+            operand: &operand,
+        };
+
         let bytes = instruction.compile().unwrap();
         assert!(vec_compare(
             &[
                 IntermediateCode::Byte(0x0f),
-                IntermediateCode::Byte(0x84),
-                IntermediateCode::Displacement32("test_label".to_string())
+                IntermediateCode::Byte(0x95),
+                IntermediateCode::Byte(0xc1),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_move_if() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⚫".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::MoveIf),
+            value: "⬅≤u".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🟣".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionMoveIf {
+            register: &register,
+            operation: &operation,
+            operand: Some(&operand),
+            memory: None,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x0f),
+                IntermediateCode::Byte(0x46),
+                IntermediateCode::Byte(0xd7),
             ],
             &bytes
         ));
     }
 
+    #[test]
+    fn test_jump_if_counter_zero() {
+        let operation = Token {
+            t: Some(TokenType::JumpIfCounterZero),
+            value: "🦘🔵0".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::LabelReference),
+            value: "test_label".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionJumpIf {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Branch {
+                short_opcode: 0xe3,
+                near_opcode: None,
+                label: "test_label".to_string(),
+            }],
+            &bytes
+        ));
+    }
+
     #[test]
     fn test_call() {
         let operation = Token {
             t: Some(TokenType::Call),
             value: "📞".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::LabelReference),
             value: "test_label".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionCall {
             operation: &operation,
@@ -1284,11 +2442,49 @@ mod test_instructions {
         ));
     }
 
+    #[test]
+    fn test_jump_call_register() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "🔴".to_string(),
+            ..Default::default()
+        };
+        let jump = Token {
+            t: Some(TokenType::Jump),
+            value: "🦘".to_string(),
+            ..Default::default()
+        };
+        let call = Token {
+            t: Some(TokenType::Call),
+            value: "📞".to_string(),
+            ..Default::default()
+        };
+
+        let instruction = InstructionJump {
+            operation: &jump,
+            operand: &register,
+        };
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xff), IntermediateCode::Byte(0xe3)],
+            &instruction.compile().unwrap()
+        ));
+
+        let instruction = InstructionCall {
+            operation: &call,
+            operand: &register,
+        };
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xff), IntermediateCode::Byte(0xd3)],
+            &instruction.compile().unwrap()
+        ));
+    }
+
     #[test]
     fn test_ret() {
         let operation = Token {
             t: Some(TokenType::Return),
             value: "↩".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionReturn {
             operation: &operation,
@@ -1303,10 +2499,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Push),
             value: "📥".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "174285409".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionPush {
             operation: &operation,
@@ -1326,15 +2524,44 @@ mod test_instructions {
         ));
     }
 
+    #[test]
+    fn test_push_section() {
+        let operation = Token {
+            t: Some(TokenType::Push),
+            value: "📥".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::SectionReference),
+            value: "numbers".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionPush {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x68),
+                IntermediateCode::Absolute32("numbers".to_string()),
+            ],
+            &bytes
+        ));
+    }
+
     #[test]
     fn test_push_register() {
         let operation = Token {
             t: Some(TokenType::Push),
             value: "📥".to_string(),
+            ..Default::default()
         };
         let register = Token {
             t: Some(TokenType::Register),
             value: "⬇".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionPush {
             operation: &operation,
@@ -1350,19 +2577,21 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Push),
             value: "📥".to_string(),
+            ..Default::default()
         };
         let register = Token {
             t: Some(TokenType::Register),
             value: "⬇".to_string(),
+            ..Default::default()
         };
         let offset = Token {
             t: Some(TokenType::Value),
             value: "-4".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionPushModRM {
             operation: &operation,
-            register: &register,
-            offset: &offset,
+            memory: memory(&offset, &register),
         };
 
         let bytes = instruction.compile().unwrap();
@@ -1381,10 +2610,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Pop),
             value: "📤".to_string(),
+            ..Default::default()
         };
         let register = Token {
             t: Some(TokenType::Register),
             value: "⬇".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionPop {
             operation: &operation,
@@ -1400,14 +2631,17 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Compare),
             value: "⚖".to_string(),
+            ..Default::default()
         };
         let left_operand = Token {
             t: Some(TokenType::Register),
             value: "🔴".to_string(),
+            ..Default::default()
         };
         let right_operand = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionCompare {
             operation: &operation,
@@ -1427,14 +2661,17 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Compare),
             value: "⚖".to_string(),
+            ..Default::default()
         };
         let left_operand = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let right_operand = Token {
             t: Some(TokenType::Value),
             value: "5".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionCompare {
             operation: &operation,
@@ -1453,15 +2690,56 @@ mod test_instructions {
         ));
     }
 
+    #[test]
+    fn test_compare_immediate_boundaries() {
+        let operation = Token {
+            t: Some(TokenType::Compare),
+            value: "⚖".to_string(),
+            ..Default::default()
+        };
+        let left_operand = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+
+        for (value, expected) in &[
+            ("127", vec![0x83, 0xf8, 0x7f]),
+            ("128", vec![0x81, 0xf8, 0x80, 0x00, 0x00, 0x00]),
+            ("-128", vec![0x83, 0xf8, 0x80]),
+            ("-129", vec![0x81, 0xf8, 0x7f, 0xff, 0xff, 0xff]),
+            ("1000", vec![0x81, 0xf8, 0xe8, 0x03, 0x00, 0x00]),
+        ] {
+            let right_operand = Token {
+                t: Some(TokenType::Value),
+                value: value.to_string(),
+                ..Default::default()
+            };
+            let instruction = InstructionCompare {
+                operation: &operation,
+                left_operand: &left_operand,
+                right_operand: &right_operand,
+            };
+
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|b| IntermediateCode::Byte(*b))
+                .collect();
+            assert!(vec_compare(&expected, &instruction.compile().unwrap()));
+        }
+    }
+
     #[test]
     fn test_interrupt_linux() {
         let operation = Token {
             t: Some(TokenType::Interrupt),
             value: "❗".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "128".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionInterrupt {
             operation: &operation,
@@ -1480,10 +2758,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Interrupt),
             value: "❗".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "$123".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionInterrupt {
             operation: &operation,
@@ -1499,10 +2779,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Interrupt),
             value: "❗".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Add),
             value: "️".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionInterrupt {
             operation: &operation,
@@ -1518,10 +2800,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Interrupt),
             value: "❗".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: None,
             value: "".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionInterrupt {
             operation: &operation,
@@ -1537,10 +2821,12 @@ mod test_instructions {
         let operation = Token {
             t: Some(TokenType::Push),
             value: "📥".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "123".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionPush {
             operation: &operation,
@@ -1556,14 +2842,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Register),
             value: "⚪".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "0".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -1580,14 +2869,17 @@ mod test_instructions {
         let register = Token {
             t: Some(TokenType::Value),
             value: "123".to_string(),
+            ..Default::default()
         };
         let operation = Token {
             t: Some(TokenType::Move),
             value: "⬅".to_string(),
+            ..Default::default()
         };
         let operand = Token {
             t: Some(TokenType::Value),
             value: "0".to_string(),
+            ..Default::default()
         };
         let instruction = InstructionMove {
             register: &register,
@@ -1598,88 +2890,1091 @@ mod test_instructions {
         let result = instruction.validate();
         assert!(result.is_err());
     }
-}
 
-pub fn compile(tokens: Vec<Token>) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
-    let mut operation: Option<Box<dyn Instruction>> = None;
+    #[test]
+    fn test_store_section_register() {
+        let destination = Token {
+            t: Some(TokenType::SectionReference),
+            value: "numbers".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionStore {
+            destination: &destination,
+            size: None,
+            operation: &operation,
+            operand: &operand,
+        };
 
-    for token in tokens.iter() {
-        operation = match token.t {
-            Some(TokenType::Interrupt) => Some(Box::new(InstructionInterrupt {
-                operation: &tokens[0],
-                operand: &tokens[1],
-            })),
-            Some(TokenType::Add) | Some(TokenType::Subtract) => {
-                Some(Box::new(InstructionAddSubtract {
-                    register: &tokens[0],
-                    operation: &tokens[1],
-                    operand: &tokens[2],
-                }))
-            }
-            Some(TokenType::Multiply) => Some(Box::new(InstructionMultiply {
-                register: &tokens[0],
-                operation: &tokens[1],
-                operand: &tokens[2],
-            })),
-            Some(TokenType::Jump) => Some(Box::new(InstructionJump {
-                operation: &tokens[0],
-                operand: &tokens[1],
-            })),
-            Some(TokenType::Pop) => Some(Box::new(InstructionPop {
-                operation: &tokens[0],
-                operand: &tokens[1],
-            })),
-            Some(TokenType::Compare) => Some(Box::new(InstructionCompare {
-                operation: &tokens[0],
-                left_operand: &tokens[1],
-                right_operand: &tokens[2],
-            })),
-            Some(TokenType::JumpIfEqual)
-            | Some(TokenType::JumpIfNotEqual)
-            | Some(TokenType::JumpIfLess)
-            | Some(TokenType::JumpIfLessEqual)
-            | Some(TokenType::JumpIfGreater)
-            | Some(TokenType::JumpIfGreaterEqual) => Some(Box::new(InstructionJumpIf {
-                operation: &tokens[0],
-                operand: &tokens[1],
-            })),
-            Some(TokenType::Call) => Some(Box::new(InstructionCall {
-                operation: &tokens[0],
-                operand: &tokens[1],
-            })),
-            Some(TokenType::Return) => Some(Box::new(InstructionReturn {
-                operation: &tokens[0],
-            })),
-            Some(TokenType::Push) => {
-                if tokens.len() == 2 {
-                    Some(Box::new(InstructionPush {
-                        operation: &tokens[0],
-                        operand: &tokens[1],
-                    }))
-                } else {
-                    Some(Box::new(InstructionPushModRM {
-                        operation: &tokens[0],
-                        offset: &tokens[1],
-                        register: &tokens[2],
-                    }))
-                }
-            }
-            Some(TokenType::Move) => {
-                if tokens.len() == 3 {
-                    Some(Box::new(InstructionMove {
-                        register: &tokens[0],
-                        operation: &tokens[1],
-                        operand: &tokens[2],
-                    }))
-                } else {
-                    Some(Box::new(InstructionMoveModRM {
-                        register: &tokens[0],
-                        operation: &tokens[1],
-                        offset: &tokens[2],
-                        operand: &tokens[3],
-                    }))
-                }
-            }
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x89),
+                IntermediateCode::Byte(0x0d),
+                IntermediateCode::Absolute32("numbers".to_string()),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_store_section_immediate() {
+        let destination = Token {
+            t: Some(TokenType::SectionReference),
+            value: "numbers".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "258".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionStore {
+            destination: &destination,
+            size: None,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0xc7),
+                IntermediateCode::Byte(0x05),
+                IntermediateCode::Absolute32("numbers".to_string()),
+                IntermediateCode::Byte(0x02),
+                IntermediateCode::Byte(0x01),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_store_modrm_register() {
+        let offset = Token {
+            t: Some(TokenType::Value),
+            value: "8".to_string(),
+            ..Default::default()
+        };
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⬇".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔴".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionStoreModRM {
+            memory: memory(&offset, &register),
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x89),
+                IntermediateCode::Byte(0x5d),
+                IntermediateCode::Byte(0x08)
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_store_modrm_immediate() {
+        let offset = Token {
+            t: Some(TokenType::Value),
+            value: "4".to_string(),
+            ..Default::default()
+        };
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "7".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionStoreModRM {
+            memory: memory(&offset, &register),
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0xc7),
+                IntermediateCode::Byte(0x41),
+                IntermediateCode::Byte(0x04),
+                IntermediateCode::Byte(0x07),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_store_validate_err() {
+        let destination = Token {
+            t: Some(TokenType::Value),
+            value: "123".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Move),
+            value: "⬅".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionStore {
+            destination: &destination,
+            size: None,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let result = instruction.validate();
+        assert!(result.is_err());
+    }
+
+    fn divide_tokens(register: &str, operation: TokenType, operand: &str) -> [Token; 3] {
+        [
+            Token {
+                t: Some(TokenType::Register),
+                value: register.to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(operation),
+                value: "➗".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Register),
+                value: operand.to_string(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_divide() {
+        let tokens = divide_tokens("⚪", TokenType::Divide, "🔵");
+        let instruction = InstructionDivide {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x99),
+                IntermediateCode::Byte(0xf7),
+                IntermediateCode::Byte(0xf9),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_remainder_unsigned() {
+        let tokens = divide_tokens("⚪", TokenType::RemainderUnsigned, "🔴");
+        let instruction = InstructionDivide {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x31),
+                IntermediateCode::Byte(0xd2),
+                IntermediateCode::Byte(0xf7),
+                IntermediateCode::Byte(0xf3),
+                IntermediateCode::Byte(0x89),
+                IntermediateCode::Byte(0xd0),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_divide_validate_err() {
+        let tokens = divide_tokens("🔴", TokenType::Divide, "🔵");
+        let instruction = InstructionDivide {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+        let error = instruction.validate().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Diagnostic>().unwrap().msg,
+            "➗ can only be used with ⚪ on the left"
+        );
+
+        let tokens = divide_tokens("⚪", TokenType::Remainder, "⚫");
+        let instruction = InstructionDivide {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+        let error = instruction.validate().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Diagnostic>().unwrap().msg,
+            "➗ can't be used to divide by ⚫"
+        );
+    }
+
+    #[test]
+    fn test_and_immediate1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⚫".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::And),
+            value: "∧".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "255".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x81),
+                IntermediateCode::Byte(0b11100000 | instruction.get_reg_value(&register).unwrap()),
+                IntermediateCode::Byte(0xff),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_or_immediate1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Or),
+            value: "∨".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "256".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x81),
+                IntermediateCode::Byte(0b11001000 | instruction.get_reg_value(&register).unwrap()),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x01),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_xor_immediate1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Xor),
+            value: "⊻".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "1".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x81),
+                IntermediateCode::Byte(0xf0),
+                IntermediateCode::Byte(0x01),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_and_register1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::And),
+            value: "∧".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔴".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0x21), IntermediateCode::Byte(0xd8)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_or_register1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Or),
+            value: "∨".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "⚫".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0x09), IntermediateCode::Byte(0xd1)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_xor_register1() {
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let operation = Token {
+            t: Some(TokenType::Xor),
+            value: "⊻".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionLogic {
+            register: &register,
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0x31), IntermediateCode::Byte(0xc0)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_not1() {
+        let operation = Token {
+            t: Some(TokenType::Not),
+            value: "¬".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔴".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionNot {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xf7), IntermediateCode::Byte(0xd3)],
+            &bytes
+        ));
+    }
+
+    fn shift_tokens(operation: TokenType, operand_type: TokenType, operand: &str) -> [Token; 3] {
+        [
+            Token {
+                t: Some(TokenType::Register),
+                value: "🔴".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(operation),
+                value: "≪".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(operand_type),
+                value: operand.to_string(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_shift_left_immediate() {
+        let tokens = shift_tokens(TokenType::ShiftLeft, TokenType::Value, "3");
+        let instruction = InstructionShift {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0xc1),
+                IntermediateCode::Byte(0xe3),
+                IntermediateCode::Byte(0x03),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_shift_right_arithmetic_one() {
+        let tokens = shift_tokens(TokenType::ShiftRightArithmetic, TokenType::Value, "1");
+        let instruction = InstructionShift {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xd1), IntermediateCode::Byte(0xfb)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_rotate_register() {
+        let tokens = shift_tokens(TokenType::RotateRight, TokenType::Register, "🔵");
+        let instruction = InstructionShift {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xd3), IntermediateCode::Byte(0xcb)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_shift_errors() {
+        let tokens = shift_tokens(TokenType::ShiftRight, TokenType::Register, "⚫");
+        let instruction = InstructionShift {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+        let error = instruction.compile().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Diagnostic>().unwrap().msg,
+            "≪ can only shift by a count in 🔵, not ⚫"
+        );
+
+        let tokens = shift_tokens(TokenType::ShiftRight, TokenType::Value, "32");
+        let instruction = InstructionShift {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[2],
+        };
+        let error = instruction.compile().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Diagnostic>().unwrap().msg,
+            "Shift count 32 should be at most 31"
+        );
+    }
+
+    #[test]
+    fn test_increment1() {
+        let operation = Token {
+            t: Some(TokenType::Increment),
+            value: "🔼".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "🔵".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionIncrementDecrement {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(&[IntermediateCode::Byte(0x41)], &bytes));
+    }
+
+    #[test]
+    fn test_decrement1() {
+        let operation = Token {
+            t: Some(TokenType::Decrement),
+            value: "🔽".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "⬇".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionIncrementDecrement {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(&[IntermediateCode::Byte(0x4d)], &bytes));
+    }
+
+    #[test]
+    fn test_negate1() {
+        let operation = Token {
+            t: Some(TokenType::Negate),
+            value: "±".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Register),
+            value: "⚪".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionNegate {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0xf7), IntermediateCode::Byte(0xd8)],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_increment_validate_err() {
+        let operation = Token {
+            t: Some(TokenType::Increment),
+            value: "🔼".to_string(),
+            ..Default::default()
+        };
+        let operand = Token {
+            t: Some(TokenType::Value),
+            value: "1".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionIncrementDecrement {
+            operation: &operation,
+            operand: &operand,
+        };
+
+        assert!(instruction.validate().is_err());
+    }
+
+    #[test]
+    fn test_memory_operand() {
+        let tokens = [
+            Token {
+                t: Some(TokenType::Value),
+                value: "8".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Register),
+                value: "⬇".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Register),
+                value: "🔵".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::DataWidth),
+                value: "8".to_string(),
+                ..Default::default()
+            },
+        ];
+        let missing = Token::default();
+
+        let memory = MemoryOperand::new(&tokens, &missing);
+        assert_eq!(memory.index.unwrap().value, "🔵");
+        assert!(memory.scale.is_none());
+        assert_eq!(memory.size.unwrap().t, Some(TokenType::DataWidth));
+
+        let memory = MemoryOperand::new(&tokens[..2], &missing);
+        assert!(memory.index.is_none());
+        assert!(memory.size.is_none());
+
+        // size directly after the base
+        let sized = [tokens[0].clone(), tokens[1].clone(), tokens[3].clone()];
+        let memory = MemoryOperand::new(&sized, &missing);
+        assert!(memory.index.is_none());
+        assert!(memory.size.is_some());
+
+        let memory = MemoryOperand::new(&tokens[..1], &missing);
+        assert!(memory.base.unwrap().t.is_none());
+
+        // index and scale without a base
+        let unbased = [tokens[0].clone(), tokens[2].clone(), tokens[0].clone()];
+        let memory = MemoryOperand::new(&unbased, &missing);
+        assert!(memory.base.is_none());
+        assert_eq!(memory.index.unwrap().value, "🔵");
+        assert_eq!(memory.scale.unwrap().value, "8");
+    }
+
+    fn move_modrm_tokens(register: &str, offset: &str, base: &str) -> [Token; 4] {
+        [
+            Token {
+                t: Some(TokenType::Register),
+                value: register.to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Move),
+                value: "⬅".to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Value),
+                value: offset.to_string(),
+                ..Default::default()
+            },
+            Token {
+                t: Some(TokenType::Register),
+                value: base.to_string(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_move_modrm_esp() {
+        let tokens = move_modrm_tokens("🟢", "4", "◀");
+        let instruction = InstructionMoveModRM {
+            register: &tokens[0],
+            operation: &tokens[1],
+            memory: memory(&tokens[2], &tokens[3]),
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x8b),
+                IntermediateCode::Byte(0x74),
+                IntermediateCode::Byte(0x24),
+                IntermediateCode::Byte(0x04),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_move_modrm_displacement_boundaries() {
+        for (offset, expected) in &[
+            ("127", vec![0x8b, 0x41, 0x7f]),
+            ("128", vec![0x8b, 0x81, 0x80, 0x00, 0x00, 0x00]),
+            ("-128", vec![0x8b, 0x41, 0x80]),
+            ("-129", vec![0x8b, 0x81, 0x7f, 0xff, 0xff, 0xff]),
+        ] {
+            let tokens = move_modrm_tokens("⚪", offset, "🔵");
+            let instruction = InstructionMoveModRM {
+                register: &tokens[0],
+                operation: &tokens[1],
+                memory: memory(&tokens[2], &tokens[3]),
+            };
+
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|b| IntermediateCode::Byte(*b))
+                .collect();
+            assert!(vec_compare(&expected, &instruction.compile().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_move_modrm_no_offset() {
+        let tokens = move_modrm_tokens("🟣", "0", "🟢");
+        let instruction = InstructionMoveModRM {
+            register: &tokens[0],
+            operation: &tokens[1],
+            memory: memory(&tokens[2], &tokens[3]),
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0x8b), IntermediateCode::Byte(0x3e)],
+            &bytes
+        ));
+
+        // mod 00 with ebp would be a 32 bit address
+        let tokens = move_modrm_tokens("⚪", "0", "⬇");
+        let instruction = InstructionMoveModRM {
+            register: &tokens[0],
+            operation: &tokens[1],
+            memory: memory(&tokens[2], &tokens[3]),
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0x8b),
+                IntermediateCode::Byte(0x45),
+                IntermediateCode::Byte(0x00),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_push_modrm_esp() {
+        let operation = Token {
+            t: Some(TokenType::Push),
+            value: "📥".to_string(),
+            ..Default::default()
+        };
+        let register = Token {
+            t: Some(TokenType::Register),
+            value: "◀".to_string(),
+            ..Default::default()
+        };
+        let offset = Token {
+            t: Some(TokenType::Value),
+            value: "0".to_string(),
+            ..Default::default()
+        };
+        let instruction = InstructionPushModRM {
+            operation: &operation,
+            memory: memory(&offset, &register),
+        };
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[
+                IntermediateCode::Byte(0xff),
+                IntermediateCode::Byte(0x34),
+                IntermediateCode::Byte(0x24),
+            ],
+            &bytes
+        ));
+    }
+
+    #[test]
+    fn test_esi_edi() {
+        let tokens = move_modrm_tokens("🟢", "0", "🟣");
+        let instruction = InstructionMove {
+            register: &tokens[0],
+            operation: &tokens[1],
+            operand: &tokens[3],
+        };
+        assert_eq!(instruction.get_reg_value(&tokens[0]).unwrap(), 6);
+        assert_eq!(instruction.get_reg_value(&tokens[3]).unwrap(), 7);
+
+        let bytes = instruction.compile().unwrap();
+        assert!(vec_compare(
+            &[IntermediateCode::Byte(0x89), IntermediateCode::Byte(0xfe)],
+            &bytes
+        ));
+    }
+}
+
+pub fn compile(tokens: Vec<Token>) -> Result<Vec<IntermediateCode>, Box<dyn error::Error>> {
+    // Operands that were not given are represented by an untyped
+    // token right after the last one. Instruction validation will
+    // report them.
+    let missing = Token {
+        t: None,
+        value: "".to_string(),
+        span: match tokens.last() {
+            Some(last) => Span {
+                start: last.span.end,
+                end: last.span.end + 1,
+                ..last.span.clone()
+            },
+            None => Span::default(),
+        },
+    };
+    let nth = |i: usize| tokens.get(i).unwrap_or(&missing);
+    let mut operation: Option<Box<dyn Instruction>> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        operation = match token.t {
+            Some(TokenType::Interrupt) => Some(Box::new(InstructionInterrupt {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Add) | Some(TokenType::Subtract) => {
+                Some(Box::new(InstructionAddSubtract {
+                    register: nth(0),
+                    operation: nth(1),
+                    operand: nth(2),
+                }))
+            }
+            Some(TokenType::Multiply) => Some(Box::new(InstructionMultiply {
+                register: nth(0),
+                operation: nth(1),
+                operand: nth(2),
+            })),
+            Some(TokenType::Divide)
+            | Some(TokenType::DivideUnsigned)
+            | Some(TokenType::Remainder)
+            | Some(TokenType::RemainderUnsigned) => Some(Box::new(InstructionDivide {
+                register: nth(0),
+                operation: nth(1),
+                operand: nth(2),
+            })),
+            Some(TokenType::And) | Some(TokenType::Or) | Some(TokenType::Xor) => {
+                Some(Box::new(InstructionLogic {
+                    register: nth(0),
+                    operation: nth(1),
+                    operand: nth(2),
+                }))
+            }
+            Some(TokenType::ShiftLeft)
+            | Some(TokenType::ShiftRight)
+            | Some(TokenType::ShiftRightArithmetic)
+            | Some(TokenType::RotateLeft)
+            | Some(TokenType::RotateRight) => Some(Box::new(InstructionShift {
+                register: nth(0),
+                operation: nth(1),
+                operand: nth(2),
+            })),
+            Some(TokenType::Increment) | Some(TokenType::Decrement) => {
+                Some(Box::new(InstructionIncrementDecrement {
+                    operation: nth(0),
+                    operand: nth(1),
+                }))
+            }
+            Some(TokenType::Negate) => Some(Box::new(InstructionNegate {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Not) => Some(Box::new(InstructionNot {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Jump) | Some(TokenType::Call) if tokens.len() > 2 => {
+                Some(Box::new(InstructionJumpModRM {
+                    operation: nth(0),
+                    memory: MemoryOperand::new(&tokens[1..], &missing),
+                }))
+            }
+            Some(TokenType::Jump) => Some(Box::new(InstructionJump {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Pop) => Some(Box::new(InstructionPop {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Compare) => Some(Box::new(InstructionCompare {
+                operation: nth(0),
+                left_operand: nth(1),
+                right_operand: nth(2),
+            })),
+            Some(TokenType::JumpIf) | Some(TokenType::JumpIfCounterZero) => {
+                Some(Box::new(InstructionJumpIf {
+                    operation: nth(0),
+                    operand: nth(1),
+                }))
+            }
+            Some(TokenType::Call) => Some(Box::new(InstructionCall {
+                operation: nth(0),
+                operand: nth(1),
+            })),
+            Some(TokenType::Return) => Some(Box::new(InstructionReturn { operation: nth(0) })),
+            Some(TokenType::Push) => {
+                if tokens.len() <= 2 {
+                    Some(Box::new(InstructionPush {
+                        operation: nth(0),
+                        operand: nth(1),
+                    }))
+                } else {
+                    Some(Box::new(InstructionPushModRM {
+                        operation: nth(0),
+                        memory: MemoryOperand::new(&tokens[1..], &missing),
+                    }))
+                }
+            }
+            // Stores have the memory operand on the left.
+            // The destination is either a section or an offset and a
+            // register, optionally followed by a size.
+            Some(TokenType::Move) if nth(0).t != Some(TokenType::Register) => match i {
+                1 => Some(Box::new(InstructionStore {
+                    destination: nth(0),
+                    size: None,
+                    operation: nth(1),
+                    operand: nth(2),
+                })),
+                2 if nth(1).t == Some(TokenType::DataWidth) => Some(Box::new(InstructionStore {
+                    destination: nth(0),
+                    size: Some(nth(1)),
+                    operation: nth(2),
+                    operand: nth(3),
+                })),
+                _ => Some(Box::new(InstructionStoreModRM {
+                    memory: MemoryOperand::new(&tokens[..i], &missing),
+                    operation: nth(i),
+                    operand: nth(i + 1),
+                })),
+            },
+            Some(TokenType::Move) => {
+                if tokens.len() <= 3 {
+                    Some(Box::new(InstructionMove {
+                        register: nth(0),
+                        operation: nth(1),
+                        operand: nth(2),
+                    }))
+                } else {
+                    Some(Box::new(InstructionMoveModRM {
+                        register: nth(0),
+                        operation: nth(1),
+                        memory: MemoryOperand::new(&tokens[2..], &missing),
+                    }))
+                }
+            }
+            Some(TokenType::MoveZeroExtend) | Some(TokenType::MoveSignExtend) => {
+                if tokens.len() <= 3 {
+                    Some(Box::new(InstructionMoveExtend {
+                        register: nth(0),
+                        operation: nth(1),
+                        operand: Some(nth(2)),
+                        memory: None,
+                    }))
+                } else {
+                    Some(Box::new(InstructionMoveExtend {
+                        register: nth(0),
+                        operation: nth(1),
+                        operand: None,
+                        memory: Some(MemoryOperand::new(&tokens[2..], &missing)),
+                    }))
+                }
+            }
+            Some(TokenType::MoveIf) => {
+                if tokens.len() <= 3 {
+                    Some(Box::new(InstructionMoveIf {
+                        register: nth(0),
+                        operation: nth(1),
+                        operand: Some(nth(2)),
+                        memory: None,
+                    }))
+                } else {
+                    Some(Box::new(InstructionMoveIf {
+                        register: nth(0),
+                        operation: nth(1),
+                        operand: None,
+                        memory: Some(MemoryOperand::new(&tokens[2..], &missing)),
+                    }))
+                }
+            }
+            Some(TokenType::SetIf) => Some(Box::new(InstructionSetIf {
+                operation: nth(0),
+                operand: nth(1),
+            })),
             _ => None,
         };
 
@@ -1688,16 +3983,15 @@ pub fn compile(tokens: Vec<Token>) -> Result<Vec<IntermediateCode>, Box<dyn erro
         }
     }
 
-    if operation.is_some() {
-        let operation = operation.unwrap();
-        operation.compile()
-    } else {
-        Err(Box::new(CompileError {
-            msg: format!(
+    match operation {
+        Some(operation) => operation.compile(),
+        None => Err(Box::new(Diagnostic::error(
+            format!(
                 "Grammatical error: {}, expected instruction",
                 tokens.iter().fold("".to_string(), |acc, t| acc.to_owned()
                     + &format!(" {}", t.value))
             ),
-        }))
+            span_of(&tokens),
+        ))),
     }
 }
@@ -11,20 +11,239 @@
 
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Stop reporting errors after this many. 0 means no limit.
+pub const DEFAULT_ERROR_LIMIT: usize = 20;
+
+pub const DEFAULT_OUTPUT: &str = "a.out";
+
+// Input filename that makes the source be read from stdin.
+pub const STDIN_FILENAME: &str = "-";
+
+// The kind of ELF file that is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emit {
+    // A statically linked executable that can be run directly.
+    Executable,
+    // A relocatable object file (ET_REL) that can be linked with ld.
+    Object,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Config {
-    pub filename: String,
+    // Source files, assembled as if they were one file in the given
+    // order.
+    pub filenames: Vec<String>,
+    pub output: String,
+    pub emit: Emit,
+    // Leave out the symbol table and debug information of
+    // executables.
+    pub strip: bool,
+    pub quiet: bool,
+    pub verbose: bool,
+    pub help: bool,
+    pub version: bool,
+    pub error_limit: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            filenames: vec![],
+            output: DEFAULT_OUTPUT.to_string(),
+            emit: Emit::Executable,
+            strip: false,
+            quiet: false,
+            verbose: false,
+            help: false,
+            version: false,
+            error_limit: DEFAULT_ERROR_LIMIT,
+        }
+    }
 }
 
 impl Config {
     pub fn new(mut args: Vec<String>) -> Result<Config, String> {
         let program_name = args.remove(0);
+        let usage_error = |msg: String| format!("{}\n\n{}", msg, Config::usage(&program_name));
+
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        let mut only_filenames = false;
+
+        while let Some(arg) = args.next() {
+            if only_filenames || arg == STDIN_FILENAME || !arg.starts_with('-') {
+                config.filenames.push(arg);
+                continue;
+            }
 
-        if args.is_empty() {
-            Err(format!("Usage: {} program.jas", program_name))
-        } else {
-            Ok(Config {
-                filename: args.remove(0),
-            })
+            // Support both --option value and --option=value.
+            let (flag, inline_value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => {
+                    (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| usage_error(format!("{} requires a value", flag)))
+            };
+
+            match flag.as_str() {
+                "--" => only_filenames = true,
+                "-o" | "--output" => config.output = value()?,
+                "--emit" => {
+                    config.emit = match value()?.as_str() {
+                        "exe" => Emit::Executable,
+                        "obj" => Emit::Object,
+                        emit => {
+                            return Err(usage_error(format!(
+                                "Invalid --emit {}, should be exe or obj",
+                                emit
+                            )))
+                        }
+                    }
+                }
+                "-s" | "--strip" => config.strip = true,
+                "-q" | "--quiet" => config.quiet = true,
+                "-v" | "--verbose" => config.verbose = true,
+                "-h" | "--help" => config.help = true,
+                "-V" | "--version" => config.version = true,
+                "--error-limit" => {
+                    let limit = value()?;
+                    config.error_limit = limit.parse().map_err(|_| {
+                        usage_error(format!("Invalid error limit {}, should be a number", limit))
+                    })?;
+                }
+                _ => return Err(usage_error(format!("Unknown option {}", arg))),
+            }
+        }
+
+        if config.quiet && config.verbose {
+            return Err(usage_error(
+                "--quiet and --verbose can't be used together".to_string(),
+            ));
         }
+
+        if config.filenames.is_empty() && !config.help && !config.version {
+            return Err(Config::usage(&program_name));
+        }
+
+        Ok(config)
+    }
+
+    pub fn usage(program_name: &str) -> String {
+        format!(
+            "Usage: {} [options] program.jas...
+
+Use - as filename to read from stdin. Multiple files are assembled
+as one program.
+
+Options:
+  -o, --output FILE      write the executable to FILE (default: {})
+      --emit exe|obj     write an executable or a relocatable object file
+                         that can be linked with ld (default: exe)
+  -s, --strip            don't add a symbol table and debug information
+                         to the executable
+  -q, --quiet            only print errors
+  -v, --verbose          print details about the generated executable
+      --error-limit N    stop after N errors, 0 means no limit (default: {})
+  -h, --help             print this help
+  -V, --version          print the version",
+            program_name, DEFAULT_OUTPUT, DEFAULT_ERROR_LIMIT
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_config {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        let mut all_args = vec!["jvo-asm".to_string()];
+        all_args.extend(args.iter().map(|arg| arg.to_string()));
+        Config::new(all_args)
+    }
+
+    #[test]
+    fn test_filename() {
+        let config = parse(&["test.jas"]).unwrap();
+        assert_eq!(config.filenames, vec!["test.jas"]);
+        assert_eq!(config.output, DEFAULT_OUTPUT);
+    }
+
+    #[test]
+    fn test_no_filename() {
+        assert!(parse(&[]).unwrap_err().starts_with("Usage: jvo-asm"));
+        assert!(parse(&["-q"]).is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let config = parse(&["-o", "out", "a.jas", "-q", "-", "b.jas"]).unwrap();
+        assert_eq!(config.filenames, vec!["a.jas", "-", "b.jas"]);
+        assert_eq!(config.output, "out");
+        assert!(config.quiet);
+        assert!(!config.verbose);
+    }
+
+    #[test]
+    fn test_long_options() {
+        let config = parse(&["--output=out", "--error-limit", "3", "--verbose", "a.jas"]).unwrap();
+        assert_eq!(config.output, "out");
+        assert_eq!(config.error_limit, 3);
+        assert!(config.verbose);
+    }
+
+    #[test]
+    fn test_emit() {
+        assert_eq!(parse(&["a.jas"]).unwrap().emit, Emit::Executable);
+        assert_eq!(
+            parse(&["--emit", "obj", "a.jas"]).unwrap().emit,
+            Emit::Object
+        );
+        assert_eq!(
+            parse(&["--emit=exe", "a.jas"]).unwrap().emit,
+            Emit::Executable
+        );
+        assert!(parse(&["--emit", "asm", "a.jas"]).is_err());
+    }
+
+    #[test]
+    fn test_strip() {
+        assert!(!parse(&["a.jas"]).unwrap().strip);
+        assert!(parse(&["-s", "a.jas"]).unwrap().strip);
+        assert!(parse(&["--strip", "a.jas"]).unwrap().strip);
+    }
+
+    #[test]
+    fn test_help_version() {
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["-V"]).unwrap().version);
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let error = parse(&["--fast", "a.jas"]).unwrap_err();
+        assert!(error.starts_with("Unknown option --fast\n\nUsage:"));
+    }
+
+    #[test]
+    fn test_missing_value() {
+        let error = parse(&["a.jas", "-o"]).unwrap_err();
+        assert!(error.starts_with("-o requires a value"));
+    }
+
+    #[test]
+    fn test_double_dash() {
+        let config = parse(&["--", "-o"]).unwrap();
+        assert_eq!(config.filenames, vec!["-o"]);
+    }
+
+    #[test]
+    fn test_quiet_verbose() {
+        assert!(parse(&["-q", "-v", "a.jas"]).is_err());
     }
 }
@@ -0,0 +1,208 @@
+// Copyright 2018, Joren Van Onder (joren.vanonder@gmail.com)
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use common::Span;
+use std::{error, fmt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Additional information attached to a diagnostic, optionally
+// pointing at another location (e.g. where a label was first
+// defined).
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub msg: String,
+    pub span: Option<Span>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub msg: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn error(msg: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            msg,
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, msg: String, span: Option<Span>) -> Diagnostic {
+        self.notes.push(Note { msg, span });
+        self
+    }
+
+    // Renders the diagnostic similar to how rustc does it:
+    //
+    // error: Invalid value: $SYS_EXIT. Should be a number.
+    //  --> examples/print.jas:3:5
+    //   |
+    // 3 | ⚪ ⬅ $SYS_EXIT
+    //   |     ^^^^^^^^^
+    //
+    // source is the content of the file the primary span points
+    // into. Snippets are only shown for spans in that file.
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.msg);
+        let file = self.span.as_ref().map(|span| span.file.as_str());
+
+        // The gutter is as wide as the largest line number shown.
+        let gutter = self
+            .notes
+            .iter()
+            .filter_map(|note| note.span.as_ref())
+            .chain(self.span.iter())
+            .map(|span| span.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        if let Some(ref span) = self.span {
+            out += &render_snippet(span, source, gutter, '^');
+        }
+
+        for note in &self.notes {
+            match note.span {
+                Some(ref span) if Some(span.file.as_str()) == file => {
+                    out += &format!("{}: {}\n", Severity::Note, note.msg);
+                    out += &render_snippet(span, source, gutter, '-');
+                }
+                Some(ref span) => {
+                    out += &format!("{} = note: {} ({})\n", " ".repeat(gutter), note.msg, span);
+                }
+                None => {
+                    out += &format!("{} = note: {}\n", " ".repeat(gutter), note.msg);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn render_snippet(span: &Span, source: &str, gutter: usize, underline: char) -> String {
+    let padding = " ".repeat(gutter);
+    let mut out = format!("{}--> {}\n", padding, span);
+
+    let line = match source.split('\n').nth(span.line.wrapping_sub(1)) {
+        Some(line) => line,
+        None => return out,
+    };
+
+    // Keep tabs so the underline lines up with the source line.
+    let indent: String = line
+        .chars()
+        .take(span.start.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = span.end.saturating_sub(span.start).max(1);
+
+    out += &format!("{} |\n", padding);
+    out += &format!("{:>gutter$} | {}\n", span.line, line, gutter = gutter);
+    out += &format!(
+        "{} | {}{}\n",
+        padding,
+        indent,
+        underline.to_string().repeat(width)
+    );
+    out
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(ref span) => write!(f, "{}: {}: {}", span, self.severity, self.msg),
+            None => write!(f, "{}: {}", self.severity, self.msg),
+        }
+    }
+}
+
+impl error::Error for Diagnostic {
+    fn description(&self) -> &str {
+        self.msg.as_str()
+    }
+}
+
+#[cfg(test)]
+mod test_diagnostic {
+    use super::*;
+
+    fn span(line: usize, start: usize, end: usize) -> Option<Span> {
+        Some(Span {
+            file: "test.jas".to_string(),
+            line,
+            start,
+            end,
+        })
+    }
+
+    #[test]
+    fn test_render_caret() {
+        let source = "🖊ST_ARG $8\n⚪ ⬅ $SYS\n";
+        let diagnostic = Diagnostic::error("Invalid value".to_string(), span(2, 5, 9));
+
+        assert_eq!(
+            diagnostic.render(source),
+            "error: Invalid value\n\
+             \x20--> test.jas:2:5\n\
+             \x20 |\n\
+             2 | ⚪ ⬅ $SYS\n\
+             \x20 |     ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_note() {
+        let source = "📪exit:\n📪exit:";
+        let diagnostic = Diagnostic::error("Label exit redefined".to_string(), span(2, 1, 7))
+            .with_note("first defined here".to_string(), span(1, 1, 7));
+        let rendered = diagnostic.render(source);
+
+        assert!(rendered.contains("note: first defined here\n --> test.jas:1:1\n"));
+        assert!(rendered.ends_with("1 | 📪exit:\n  | ------\n"));
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic =
+            Diagnostic::error("oops".to_string(), None).with_note("hint".to_string(), None);
+        assert_eq!(diagnostic.render(""), "error: oops\n = note: hint\n");
+    }
+
+    #[test]
+    fn test_display() {
+        let diagnostic = Diagnostic::error("oops".to_string(), span(3, 2, 4));
+        assert_eq!(diagnostic.to_string(), "test.jas:3:2: error: oops");
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2018, Joren Van Onder (joren.vanonder@gmail.com)
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Minimal DWARF 2 debug information: a single compile unit covering
+// all code and a line number program that maps every instruction to
+// the line it was assembled from. The line number program is version
+// 3, which added the last three standard opcodes.
+
+// tags and attributes (section 7.5.4 of the DWARF 2 spec)
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_CHILDREN_NO: u8 = 0x00;
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA4: u8 = 0x06;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+// standard opcodes of the line number program
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+// extended opcodes of the line number program
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+const ABBREV_COMPILE_UNIT: u8 = 1;
+
+// A row in the line number table: the instruction at address was
+// assembled from line in files[file].
+pub struct Row {
+    pub address: u32,
+    pub file: usize,
+    pub line: usize,
+}
+
+fn uleb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sleb128(mut value: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn push_string(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend(s.bytes());
+    bytes.push(0x00);
+}
+
+// Prefixes contents with its 32 bit length, as used by the headers
+// of both .debug_line and .debug_info.
+fn with_unit_length(contents: Vec<u8>) -> Vec<u8> {
+    let mut unit = (contents.len() as u32).to_le_bytes().to_vec();
+    unit.extend(contents);
+    unit
+}
+
+// Rows should be sorted by address. end_address is the address right
+// after the last instruction.
+pub fn create_debug_line(files: &[&str], rows: &[Row], end_address: u32) -> Vec<u8> {
+    let mut header = vec![
+        1, // minimum_instruction_length
+        1, // default_is_stmt
+        // line_base and line_range, only used by special opcodes
+        // which aren't generated
+        -5_i8 as u8,
+        14,
+        OPCODE_BASE,
+    ];
+    header.extend(&STANDARD_OPCODE_LENGTHS);
+
+    // include_directories, file names are relative to DW_AT_comp_dir
+    header.push(0x00);
+
+    // file_names, numbered from 1
+    for file in files {
+        push_string(&mut header, file);
+        header.extend(uleb128(0)); // directory
+        header.extend(uleb128(0)); // modification time
+        header.extend(uleb128(0)); // length
+    }
+    header.push(0x00);
+
+    let mut program = vec![];
+    if let Some(first) = rows.first() {
+        program.push(0x00);
+        program.extend(uleb128(5));
+        program.push(DW_LNE_SET_ADDRESS);
+        program.extend(&first.address.to_le_bytes());
+    }
+
+    // The state machine starts at file 1 (index 0), line 1.
+    let mut address = rows.first().map_or(0, |row| row.address);
+    let mut file = 0;
+    let mut line = 1;
+    for row in rows {
+        if row.file != file {
+            program.push(DW_LNS_SET_FILE);
+            program.extend(uleb128(row.file as u64 + 1));
+            file = row.file;
+        }
+        if row.line != line {
+            program.push(DW_LNS_ADVANCE_LINE);
+            program.extend(sleb128(row.line as i64 - line as i64));
+            line = row.line;
+        }
+        if row.address != address {
+            program.push(DW_LNS_ADVANCE_PC);
+            program.extend(uleb128(u64::from(row.address - address)));
+            address = row.address;
+        }
+        program.push(DW_LNS_COPY);
+    }
+
+    if !rows.is_empty() {
+        program.push(DW_LNS_ADVANCE_PC);
+        program.extend(uleb128(u64::from(end_address - address)));
+        program.push(0x00);
+        program.extend(uleb128(1));
+        program.push(DW_LNE_END_SEQUENCE);
+    }
+
+    let mut contents = vec![];
+
+    // version, 3 is the first one with 12 standard opcodes
+    contents.extend(&3_u16.to_le_bytes());
+
+    // header_length
+    contents.extend(&(header.len() as u32).to_le_bytes());
+    contents.extend(header);
+    contents.extend(program);
+
+    with_unit_length(contents)
+}
+
+pub fn create_debug_abbrev() -> Vec<u8> {
+    let mut abbrev = vec![ABBREV_COMPILE_UNIT, DW_TAG_COMPILE_UNIT, DW_CHILDREN_NO];
+    abbrev.extend(&[DW_AT_NAME, DW_FORM_STRING]);
+    abbrev.extend(&[DW_AT_COMP_DIR, DW_FORM_STRING]);
+    abbrev.extend(&[DW_AT_PRODUCER, DW_FORM_STRING]);
+    abbrev.extend(&[DW_AT_LANGUAGE, DW_FORM_DATA2]);
+    abbrev.extend(&[DW_AT_LOW_PC, DW_FORM_ADDR]);
+    abbrev.extend(&[DW_AT_HIGH_PC, DW_FORM_ADDR]);
+    abbrev.extend(&[DW_AT_STMT_LIST, DW_FORM_DATA4]);
+    abbrev.extend(&[0x00, 0x00]);
+
+    // end of abbreviations
+    abbrev.push(0x00);
+    abbrev
+}
+
+// A compile unit named name that covers the code from low_pc up to
+// high_pc. It refers to the start of .debug_abbrev and .debug_line.
+pub fn create_debug_info(name: &str, comp_dir: &str, low_pc: u32, high_pc: u32) -> Vec<u8> {
+    let mut contents = vec![];
+
+    // version
+    contents.extend(&2_u16.to_le_bytes());
+
+    // debug_abbrev_offset
+    contents.extend(&0_u32.to_le_bytes());
+
+    // address_size
+    contents.push(4);
+
+    contents.push(ABBREV_COMPILE_UNIT);
+    push_string(&mut contents, name);
+    push_string(&mut contents, comp_dir);
+    push_string(
+        &mut contents,
+        concat!("jvo-asm ", env!("CARGO_PKG_VERSION")),
+    );
+    contents.extend(&DW_LANG_MIPS_ASSEMBLER.to_le_bytes());
+    contents.extend(&low_pc.to_le_bytes());
+    contents.extend(&high_pc.to_le_bytes());
+
+    // DW_AT_stmt_list, offset in .debug_line
+    contents.extend(&0_u32.to_le_bytes());
+
+    with_unit_length(contents)
+}
+
+#[cfg(test)]
+mod test_dwarf {
+    use super::*;
+
+    #[test]
+    fn test_uleb128() {
+        assert_eq!(uleb128(2), vec![2]);
+        assert_eq!(uleb128(127), vec![0x7f]);
+        assert_eq!(uleb128(128), vec![0x80, 0x01]);
+        assert_eq!(uleb128(12857), vec![0xb9, 0x64]);
+    }
+
+    #[test]
+    fn test_sleb128() {
+        assert_eq!(sleb128(2), vec![2]);
+        assert_eq!(sleb128(-2), vec![0x7e]);
+        assert_eq!(sleb128(127), vec![0xff, 0x00]);
+        assert_eq!(sleb128(-128), vec![0x80, 0x7f]);
+    }
+
+    #[test]
+    fn test_debug_line() {
+        let rows = [
+            Row {
+                address: 0x1000,
+                file: 0,
+                line: 1,
+            },
+            Row {
+                address: 0x1005,
+                file: 0,
+                line: 3,
+            },
+        ];
+        let debug_line = create_debug_line(&["a.jas"], &rows, 0x1007);
+        let header_length = 1 + 1 + 1 + 1 + 1 + 12 + 1 + 6 + 3 + 1;
+
+        assert_eq!(&debug_line[4..6], &[0x03, 0x00]);
+        assert_eq!(&debug_line[6..10], &[header_length as u8, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            &debug_line[10 + header_length..],
+            &[
+                0x00,
+                0x05,
+                DW_LNE_SET_ADDRESS,
+                0x00,
+                0x10,
+                0x00,
+                0x00,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_LINE,
+                0x02,
+                DW_LNS_ADVANCE_PC,
+                0x05,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_PC,
+                0x02,
+                0x00,
+                0x01,
+                DW_LNE_END_SEQUENCE,
+            ]
+        );
+        assert_eq!(
+            u32::from_le_bytes([debug_line[0], debug_line[1], debug_line[2], debug_line[3]]),
+            debug_line.len() as u32 - 4
+        );
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let debug_info = create_debug_info("a.jas", "/tmp", 0x1000, 0x1007);
+
+        // version, abbrev offset, address size and abbreviation code
+        assert_eq!(
+            &debug_info[4..12],
+            &[
+                0x02,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x04,
+                ABBREV_COMPILE_UNIT
+            ]
+        );
+        assert!(debug_info.ends_with(&[0x00, 0x10, 0x00, 0x00, 0x07, 0x10, 0x00, 0x00, 0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2018, Joren Van Onder (joren.vanonder@gmail.com)
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Building blocks for 32 bit ELF files. The layout of the file itself
+// is decided by the callers.
+
+// e_type
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+
+// sh_type
+pub const SHT_NULL: u32 = 0x00;
+pub const SHT_PROGBITS: u32 = 0x01;
+pub const SHT_SYMTAB: u32 = 0x02;
+pub const SHT_STRTAB: u32 = 0x03;
+pub const SHT_NOBITS: u32 = 0x08;
+pub const SHT_REL: u32 = 0x09;
+
+// sh_flags
+pub const SHF_WRITE: u32 = 0x01;
+pub const SHF_ALLOC: u32 = 0x02;
+pub const SHF_EXECINSTR: u32 = 0x04;
+
+// st_shndx of a symbol that is defined in another object
+pub const SHN_UNDEF: u16 = 0;
+
+// symbol binding, upper 4 bits of st_info
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+
+// symbol type, lower 4 bits of st_info
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+
+// relocation types (p 1-13 of the i386 psABI)
+pub const R_386_32: u8 = 1;
+pub const R_386_PC32: u8 = 2;
+
+pub const ELF_HEADER_SIZE: u32 = 52;
+pub const PROGRAM_HEADER_ENTRY_SIZE: u32 = 32;
+pub const SECTION_HEADER_ENTRY_SIZE: u32 = 40;
+pub const SYMBOL_TABLE_ENTRY_SIZE: u32 = 16;
+pub const RELOCATION_ENTRY_SIZE: u32 = 8;
+
+// Round offset up to the next multiple of alignment.
+pub fn align(offset: u32, alignment: u32) -> u32 {
+    offset.div_ceil(alignment) * alignment
+}
+
+pub fn create_string_table(strings: &[&String]) -> Vec<u8> {
+    let mut table: Vec<u8> = vec![0x00]; // first byte is defined to be null
+    for s in strings {
+        table.extend(s.bytes());
+        table.push(0x00);
+    }
+
+    table
+}
+
+// A string table that can be built incrementally. add returns the
+// index to use in e.g. sh_name or st_name.
+pub struct StringTable {
+    pub bytes: Vec<u8>,
+}
+
+impl StringTable {
+    pub fn new() -> StringTable {
+        StringTable {
+            bytes: create_string_table(&[]),
+        }
+    }
+
+    pub fn add(&mut self, s: &str) -> u32 {
+        let index = self.bytes.len() as u32;
+        self.bytes.extend(s.bytes());
+        self.bytes.push(0x00);
+        index
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_section_header_entry(
+    sh_name: u32,
+    sh_type: u32,
+    sh_flags: u32,
+    sh_addr: u32,
+    sh_offset: u32,
+    sh_size: u32,
+    sh_link: u32,
+    sh_info: u32,
+    sh_addralign: u32,
+    sh_entsize: u32,
+) -> Vec<u8> {
+    let mut entry: Vec<u8> = vec![];
+    // typedef struct
+    // {
+    //     Elf32_Word    sh_name;                /* Section name (string tbl index) */
+    //     Elf32_Word    sh_type;                /* Section type */
+    //     Elf32_Word    sh_flags;               /* Section flags */
+    //     Elf32_Addr    sh_addr;                /* Section virtual addr at execution */
+    //     Elf32_Off     sh_offset;              /* Section file offset */
+    //     Elf32_Word    sh_size;                /* Section size in bytes */
+    //     Elf32_Word    sh_link;                /* Link to another section */
+    //     Elf32_Word    sh_info;                /* Additional section information */
+    //     Elf32_Word    sh_addralign;           /* Section alignment */
+    //     Elf32_Word    sh_entsize;             /* Entry size if section holds table */
+    // } Elf32_Shdr;
+
+    // sh_name
+    entry.extend_from_slice(&sh_name.to_le_bytes());
+
+    // sh_type
+    entry.extend_from_slice(&sh_type.to_le_bytes());
+
+    // sh_flags
+    entry.extend_from_slice(&sh_flags.to_le_bytes());
+
+    // sh_addr
+    entry.extend_from_slice(&sh_addr.to_le_bytes());
+
+    // sh_offset
+    entry.extend_from_slice(&sh_offset.to_le_bytes());
+
+    // sh_size
+    entry.extend_from_slice(&sh_size.to_le_bytes());
+
+    // sh_link
+    entry.extend_from_slice(&sh_link.to_le_bytes());
+
+    // sh_info
+    entry.extend_from_slice(&sh_info.to_le_bytes());
+
+    // sh_addralign
+    entry.extend_from_slice(&sh_addralign.to_le_bytes());
+
+    // sh_entsize
+    entry.extend_from_slice(&sh_entsize.to_le_bytes());
+
+    entry
+}
+
+pub fn create_symbol_table_entry(
+    st_name: u32,
+    st_value: u32,
+    st_size: u32,
+    binding: u8,
+    symbol_type: u8,
+    st_shndx: u16,
+) -> Vec<u8> {
+    let mut entry: Vec<u8> = vec![];
+    // typedef struct
+    // {
+    //     Elf32_Word    st_name;                /* Symbol name (string tbl index) */
+    //     Elf32_Addr    st_value;               /* Symbol value */
+    //     Elf32_Word    st_size;                /* Symbol size */
+    //     unsigned char st_info;                /* Symbol type and binding */
+    //     unsigned char st_other;               /* Symbol visibility */
+    //     Elf32_Section st_shndx;               /* Section index */
+    // } Elf32_Sym;
+
+    // st_name
+    entry.extend_from_slice(&st_name.to_le_bytes());
+
+    // st_value
+    entry.extend_from_slice(&st_value.to_le_bytes());
+
+    // st_size
+    entry.extend_from_slice(&st_size.to_le_bytes());
+
+    // st_info
+    entry.push(binding << 4 | symbol_type);
+
+    // st_other (default visibility)
+    entry.push(0x00);
+
+    // st_shndx
+    entry.extend_from_slice(&st_shndx.to_le_bytes());
+
+    entry
+}
+
+pub fn create_relocation_entry(r_offset: u32, symbol_index: u32, relocation_type: u8) -> Vec<u8> {
+    let mut entry: Vec<u8> = vec![];
+    // typedef struct
+    // {
+    //     Elf32_Addr    r_offset;               /* Address */
+    //     Elf32_Word    r_info;                 /* Relocation type and symbol index */
+    // } Elf32_Rel;
+    //
+    // The addend is stored in the bytes that are being relocated.
+
+    // r_offset
+    entry.extend_from_slice(&r_offset.to_le_bytes());
+
+    // r_info
+    entry.extend_from_slice(&(symbol_index << 8 | u32::from(relocation_type)).to_le_bytes());
+
+    entry
+}
+
+// The section header string table is always the last section.
+pub fn create_elf_header(
+    object_type: u16,
+    entry: u32,
+    number_of_program_headers: u32,
+    section_header_offset: u32,
+    number_of_sections: u32,
+) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![];
+
+    // Magic number
+    header.append(&mut vec![0x7f, 0x45, 0x4c, 0x46]);
+
+    // 32 bit
+    header.push(0x01);
+
+    // little endian
+    header.push(0x01);
+
+    // ELF version 1
+    header.push(0x01);
+
+    // Target operation system ABI (System V)
+    header.push(0x00);
+
+    // ABI version (currently unused)
+    header.push(0x00);
+
+    // EIPAD (currently unused)
+    header.append(&mut vec![0x00; 7]);
+
+    // Object file type
+    header.extend_from_slice(&object_type.to_le_bytes());
+
+    // Target architecture x86
+    header.append(&mut vec![0x03, 0x00]);
+
+    // ELF version 1
+    header.extend_from_slice(&1_u32.to_le_bytes());
+
+    // e_entry
+    header.extend_from_slice(&entry.to_le_bytes());
+
+    // e_phoff: Start of program header table (immediately after this header)
+    if number_of_program_headers > 0 {
+        header.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    } else {
+        header.extend_from_slice(&0_u32.to_le_bytes());
+    }
+
+    // e_shoff: Start of section header table
+    header.extend_from_slice(&section_header_offset.to_le_bytes());
+
+    // eflags
+    header.append(&mut vec![0x00; 4]);
+
+    // Size of this header
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+
+    // e_phentsize: size of a program header table entry
+    header.extend_from_slice(&(PROGRAM_HEADER_ENTRY_SIZE as u16).to_le_bytes());
+
+    // e_phnum: number of entries in program header table
+    header.extend_from_slice(&(number_of_program_headers as u16).to_le_bytes());
+
+    // e_shentsize: size of a section header table entry
+    header.extend_from_slice(&(SECTION_HEADER_ENTRY_SIZE as u16).to_le_bytes());
+
+    // e_shnum: number of entries in section header table
+    header.extend_from_slice(&(number_of_sections as u16).to_le_bytes());
+
+    // e_shstrndx: index of section header table entry that contains section names
+    header.extend_from_slice(&(number_of_sections as u16 - 1).to_le_bytes());
+
+    header
+}
+
+#[cfg(test)]
+mod test_elf {
+    use super::*;
+
+    #[test]
+    fn test_elf_header_length() {
+        assert_eq!(
+            create_elf_header(ET_EXEC, 0, 1, 0, 3).len(),
+            ELF_HEADER_SIZE as usize
+        );
+    }
+
+    #[test]
+    fn test_elf_header_relocatable() {
+        let header = create_elf_header(ET_REL, 0, 0, 0x100, 7);
+        assert_eq!(&header[16..18], &[0x01, 0x00]);
+        // e_phoff
+        assert_eq!(&header[28..32], &[0x00; 4]);
+        // e_shoff
+        assert_eq!(&header[32..36], &[0x00, 0x01, 0x00, 0x00]);
+        // e_shnum and e_shstrndx
+        assert_eq!(&header[48..52], &[0x07, 0x00, 0x06, 0x00]);
+    }
+
+    #[test]
+    fn test_symbol_table_entry() {
+        let entry = create_symbol_table_entry(1, 0x10, 4, STB_GLOBAL, STT_NOTYPE, 1);
+        assert_eq!(entry.len(), SYMBOL_TABLE_ENTRY_SIZE as usize);
+        assert_eq!(
+            entry,
+            vec![1, 0, 0, 0, 0x10, 0, 0, 0, 4, 0, 0, 0, 0x10, 0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_relocation_entry() {
+        let entry = create_relocation_entry(0x21, 5, R_386_PC32);
+        assert_eq!(entry.len(), RELOCATION_ENTRY_SIZE as usize);
+        assert_eq!(entry, vec![0x21, 0, 0, 0, 0x02, 0x05, 0, 0]);
+    }
+
+    #[test]
+    fn test_string_table() {
+        let mut table = StringTable::new();
+        assert_eq!(table.add(".text"), 1);
+        assert_eq!(table.add("exit"), 7);
+        assert_eq!(table.bytes, b"\0.text\0exit\0".to_vec());
+    }
+
+    #[test]
+    fn test_align() {
+        assert_eq!(align(0, 4), 0);
+        assert_eq!(align(1, 4), 4);
+        assert_eq!(align(0x1000, 0x1000), 0x1000);
+        assert_eq!(align(0x1001, 0x1000), 0x2000);
+    }
+}
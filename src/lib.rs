@@ -14,70 +14,240 @@
 mod common;
 mod compiler;
 pub mod config;
+pub mod diagnostic;
+mod dwarf;
+mod elf;
 mod tokenizer;
 
-use common::{IntermediateCode, Token, TokenType};
+use common::{span_of, IntermediateCode, Span, Token, TokenType};
 use compiler::*;
 use config::*;
-use std::collections::HashMap;
-use std::io::Write;
+use diagnostic::Diagnostic;
+use dwarf::*;
+use elf::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::os::unix::fs::PermissionsExt;
-use std::{error, fs};
+use std::{env, error, fmt, fs, io};
 use tokenizer::*;
 
 const CODE_SECTION_NAME: &str = ".code";
 const STRTAB_SECTION_NAME: &str = ".shstrtab";
 
+// Section names used in relocatable object files. These are the names
+// ld expects.
+const TEXT_SECTION_NAME: &str = ".text";
+const DATA_SECTION_NAME: &str = ".data";
+const RODATA_SECTION_NAME: &str = ".rodata";
+const BSS_SECTION_NAME: &str = ".bss";
+const SYMTAB_SECTION_NAME: &str = ".symtab";
+const SYMBOL_STRTAB_SECTION_NAME: &str = ".strtab";
+const REL_TEXT_SECTION_NAME: &str = ".rel.text";
+const REL_DATA_SECTION_NAME: &str = ".rel.data";
+const REL_RODATA_SECTION_NAME: &str = ".rel.rodata";
+const DEBUG_LINE_SECTION_NAME: &str = ".debug_line";
+const DEBUG_INFO_SECTION_NAME: &str = ".debug_info";
+const DEBUG_ABBREV_SECTION_NAME: &str = ".debug_abbrev";
+
 const DATA_SECTION_PHYSICAL_START: u32 = 0x1000;
 const STRTABLE_PHYSICAL_ENTRY_POINT: u32 = 0x400;
 const DATA_SECTION_VIRTUAL_START: u32 = 0x0804_9000;
+// The virtual address of the start of the file. Everything that is
+// loaded is mapped at this address + its file offset.
+const LOAD_ADDRESS: u32 = DATA_SECTION_VIRTUAL_START - DATA_SECTION_PHYSICAL_START;
 
 const PAGE_SIZE: u32 = 0x1000;
 
 struct DataSection {
     name: String,
     bytes: Vec<u8>,
+    // Defined with 📕, placed in .rodata.
+    read_only: bool,
+    // Label addresses in bytes, with offsets relative to the start of
+    // the section.
+    relocations: Vec<Relocation>,
+}
+
+// A named region of zeroed memory. All reservations are placed in
+// .bss, which takes no space in the file.
+struct Reservation {
+    name: String,
+    size: u32,
+}
+
+// Returns the offset of every reservation in .bss and the size of
+// .bss.
+fn reservation_offsets(reservations: &[Reservation]) -> (Vec<u32>, u32) {
+    let mut offsets = vec![];
+    let mut size = 0;
+    for reservation in reservations {
+        size = align(size, 4);
+        offsets.push(size);
+        size += reservation.size;
+    }
+
+    (offsets, size)
+}
+
+// Four bytes in the code or a data section that depend on the address
+// of a symbol. The symbol is either a label or a data section.
+#[derive(Debug, PartialEq)]
+struct Relocation {
+    offset: usize,
+    symbol: String,
+    addend: i32,
+    // If true the address is relative to the start of the four bytes
+    // (R_386_PC32), otherwise it's absolute (R_386_32).
+    relative: bool,
+}
+
+// The assembled program. Code that refers to data sections or to
+// labels defined elsewhere is left zeroed, relocations describe
+// how it should be filled in.
+struct Program {
+    code: Vec<u8>,
+    data_sections: Vec<DataSection>,
+    reservations: Vec<Reservation>,
+    // Label names with their offset in code, sorted by offset.
+    labels: Vec<(String, usize)>,
+    // Labels that are called. They are marked as functions in the
+    // symbol table.
+    functions: HashSet<String>,
+    // Labels exported with 📢. They are global symbols in object
+    // files, other labels are local.
+    exports: HashSet<String>,
+    relocations: Vec<Relocation>,
+    // Offset in code of every instruction with the line it was
+    // assembled from, sorted by offset.
+    lines: Vec<(usize, Span)>,
+}
+
+struct Source {
+    filename: String,
+    content: String,
+}
+
+fn read_source(filename: &str) -> io::Result<Source> {
+    let mut content = String::new();
+    let result = if filename == STDIN_FILENAME {
+        io::stdin().read_to_string(&mut content)
+    } else {
+        fs::File::open(filename).and_then(|mut file| file.read_to_string(&mut content))
+    };
+
+    match result {
+        Ok(_) => Ok(Source {
+            filename: if filename == STDIN_FILENAME {
+                "<stdin>".to_string()
+            } else {
+                filename.to_string()
+            },
+            content,
+        }),
+        Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", filename, e))),
+    }
+}
+
+// Errors that don't carry a location yet (e.g. failing to parse a
+// number) are attributed to span.
+fn to_diagnostic(error: Box<dyn error::Error>, span: Option<Span>) -> Diagnostic {
+    match error.downcast::<Diagnostic>() {
+        Ok(diagnostic) => *diagnostic,
+        Err(error) => Diagnostic::error(error.to_string(), span),
+    }
 }
 
-fn process(filename: &str) -> Result<Vec<DataSection>, Box<dyn error::Error>> {
-    let content = fs::read_to_string(filename)?;
+// Assembles sources as if they were a single file. Labels and
+// constants defined in one source can be used in the ones after it.
+fn process(sources: &[Source], config: &Config) -> Result<Program, Vec<Diagnostic>> {
+    let error_limit = config.error_limit;
 
-    // Contains a section for the executable code and other data
-    // sections. The executable code will have CODE_SECTION_NAME as
-    // it's key.
+    // Contains all data sections in the order they were defined.
     let mut sections: Vec<DataSection> = vec![];
 
+    // Contains all reservations in the order they were defined.
+    let mut reservations: Vec<Reservation> = vec![];
+
+    // This maps a data section or reservation name to where it was
+    // defined.
+    let mut section_spans: HashMap<String, Span> = HashMap::new();
+
+    // All errors encountered so far. Lines with errors are skipped so
+    // that the rest of the file can still be checked.
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+
     // The intermediate program consists of IntermediateCode. The
     // instructions are responsible for compiling
-    // IntermediateCode. The intermediate program is padded so that
-    // displacements take up the right amount of space (e.g. 1
-    // Displacement32 + 3 Padding). This way the index in the
-    // intermediate program vector can be used for offset
-    // calculations. Note that you cannot use the compiled program for
-    // this. You do not know addresses of instructions not yet
-    // compiled if we didn't do this padding.
+    // IntermediateCode. Branches take up 2 bytes when their label is
+    // close and more when it isn't, so byte offsets are only known
+    // once the whole program is compiled. Until then labels and
+    // instructions refer to indices in this vector.
     let mut intermediate_program: Vec<IntermediateCode> = vec![];
 
     // This maps a label String to the index in the intermediate
     // program it points to.
     let mut labels = HashMap::new();
 
-    // This holds the size of all processed data sections.
-    let mut data_section_size: usize = 0;
+    // This maps a label String to where it was defined.
+    let mut label_spans: HashMap<String, Span> = HashMap::new();
+
+    // Contains all labels that are called.
+    let mut functions = HashSet::new();
 
     // This maps constant names to the tokens they should be replaced
     // with.
     let mut constants = HashMap::new();
 
-    // This maps the index of a displacement in the intermediate
-    // program to an offset so that:
-    // displacement index - offset = index where instruction ends
-    // This is done because RIP addressing is relative to the *end* of
-    // the current instruction.
-    let mut intermediate_index_instruction_offset = HashMap::new();
+    // This maps the index of a displacement or branch in the
+    // intermediate program to the index where its instruction
+    // ends. This is done because RIP addressing is relative to the
+    // *end* of the current instruction.
+    let mut instruction_ends = HashMap::new();
+
+    // This maps the index of a displacement, branch or address in the
+    // intermediate program to the reference it was compiled from.
+    let mut reference_spans = HashMap::new();
+
+    // Labels used in data sections with where they were used. They
+    // are checked once all labels are known.
+    let mut data_label_references: Vec<(String, Span)> = vec![];
+
+    // Labels exported with 📢 with where they were exported. They are
+    // also checked once all labels are known.
+    let mut exports: Vec<(String, Span)> = vec![];
+
+    // Where each instruction starts in the intermediate program and
+    // the line it came from.
+    let mut instruction_lines = vec![];
+
+    let lines = sources.iter().flat_map(|source| {
+        source
+            .content
+            .split('\n')
+            .enumerate()
+            .map(move |(line_index, line)| (source.filename.as_str(), line_index, line))
+    });
+
+    for (filename, line_index, line) in lines {
+        // Keep going until one error past the limit, so it's known
+        // whether any were left out.
+        if error_limit > 0 && diagnostics.len() > error_limit {
+            break;
+        }
 
-    for line in content.split('\n') {
-        let mut tokens = tokenize(line)?;
+        let line_span = Span {
+            file: filename.to_string(),
+            line: line_index + 1,
+            start: 1,
+            end: line.chars().count() + 1,
+        };
+        let tokens = match tokenize(filename, line_index + 1, line) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                diagnostics.push(to_diagnostic(e, Some(line_span)));
+                continue;
+            }
+        };
         // Line was a comment.
         if tokens.is_empty() {
             continue;
@@ -87,40 +257,250 @@ fn process(filename: &str) -> Result<Vec<DataSection>, Box<dyn error::Error>> {
         // executable.
         match tokens[0].t {
             Some(TokenType::Constant) => {
-                constants.insert(tokens[0].value.clone(), tokens[1].clone());
+                match tokens.get(1) {
+                    Some(value) => {
+                        constants.insert(tokens[0].value.clone(), value.clone());
+                    }
+                    None => diagnostics.push(Diagnostic::error(
+                        format!("Constant {} is missing a value", tokens[0].value),
+                        Some(tokens[0].span.clone()),
+                    )),
+                }
                 continue;
             }
             Some(TokenType::Label) => {
+                let name = &tokens[0].value;
+                if let Some(first_span) = label_spans.get(name) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            format!("Label {} is defined more than once", name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note("first defined here".to_string(), Some(first_span.clone())),
+                    );
+                    continue;
+                }
+
+                // Relocations refer to labels and data sections by
+                // name, so they can't share one.
+                if let Some(section_span) = section_spans.get(name) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            format!("Label {} has the same name as a data section", name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note(
+                            "data section defined here".to_string(),
+                            Some(section_span.clone()),
+                        ),
+                    );
+                }
+
                 // Labels should point to the next instruction.
-                labels.insert(tokens[0].value.clone(), intermediate_program.len());
+                labels.insert(name.clone(), intermediate_program.len());
+                label_spans.insert(name.clone(), tokens[0].span.clone());
+                continue;
+            }
+            Some(TokenType::Export) => {
+                exports.push((tokens[0].value.clone(), tokens[0].span.clone()));
                 continue;
             }
-            Some(TokenType::Section) => {
+            Some(TokenType::Section) | Some(TokenType::ReadOnlySection) => {
+                let section_name = &tokens[0].value;
+                // section_spans also holds the reservations.
+                if let Some(first_span) = section_spans.get(section_name) {
+                    let diagnostic = if sections.iter().any(|s| &s.name == section_name) {
+                        Diagnostic::error(
+                            format!("Data section {} is defined more than once", section_name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note("first defined here".to_string(), Some(first_span.clone()))
+                    } else {
+                        Diagnostic::error(
+                            format!(
+                                "Data section {} has the same name as a reservation",
+                                section_name
+                            ),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note(
+                            "reservation defined here".to_string(),
+                            Some(first_span.clone()),
+                        )
+                    };
+                    diagnostics.push(diagnostic);
+                    continue;
+                }
+                if let Some(label_span) = label_spans.get(section_name) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            format!("Data section {} has the same name as a label", section_name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note("label defined here".to_string(), Some(label_span.clone())),
+                    );
+                }
+                section_spans.insert(section_name.clone(), tokens[0].span.clone());
+
                 // Sections will be referenced with Constants
-                // afterwards. Create a Value Token with the virtual
-                // address these constants will be replaced by.
-                let virtual_address = Token {
-                    t: Some(TokenType::Value),
-                    value: (DATA_SECTION_VIRTUAL_START as usize + data_section_size).to_string(),
+                // afterwards. Their address is only known when the
+                // ELF file is created, so replace them with a
+                // SectionReference.
+                let reference = Token {
+                    t: Some(TokenType::SectionReference),
+                    value: section_name.clone(),
+                    span: tokens[0].span.clone(),
                 };
-                let section_name = &tokens[0].value;
-                constants.insert(section_name.clone(), virtual_address);
-                data_section_size += PAGE_SIZE as usize; // TODO data sections are assumed to be 4KB
+                constants.insert(section_name.clone(), reference);
 
                 let mut section_data = vec![];
+                let mut section_relocations = vec![];
+                // Values are 32 bit unless changed with 📏.
+                let mut width = 32;
                 for token in &tokens[1..] {
                     match token.t {
-                        // In data sections 32 bit values are tokenized as
-                        // Memory (no preceding $).
-                        Some(TokenType::Memory) => section_data
-                            .extend_from_slice(&token.value.parse::<i32>()?.to_le_bytes()),
-                        _ => panic!("Unsupported token in data section: {:?}", token),
+                        // In data sections values are tokenized as
+                        // Memory (no preceding $). They can be signed
+                        // or unsigned.
+                        Some(TokenType::Memory) => match parse_number(&token.value) {
+                            Ok(value) if value >= -(1 << (width - 1)) && value < 1 << width => {
+                                section_data
+                                    .extend_from_slice(&(value as u32).to_le_bytes()[..width / 8]);
+                            }
+                            Ok(_) => diagnostics.push(Diagnostic::error(
+                                format!("Data value {} doesn't fit in {} bits", token.value, width),
+                                Some(token.span.clone()),
+                            )),
+                            Err(msg) => diagnostics.push(Diagnostic::error(
+                                format!("Invalid data value {}. {}", token.value, msg),
+                                Some(token.span.clone()),
+                            )),
+                        },
+                        Some(TokenType::DataWidth) => {
+                            // The tokenizer only allows 8, 16 and 32.
+                            width = token.value.parse().unwrap();
+                        }
+                        Some(TokenType::String) => {
+                            // Already validated by the tokenizer.
+                            section_data.extend(string_bytes(&token.value).unwrap());
+                        }
+                        // The address of a label, e.g. for a table of
+                        // functions.
+                        Some(TokenType::LabelReference) if width == 32 => {
+                            section_relocations.push(Relocation {
+                                offset: section_data.len(),
+                                symbol: token.value.clone(),
+                                addend: 0,
+                                relative: false,
+                            });
+                            section_data.extend(&[0; 4]);
+                            data_label_references.push((token.value.clone(), token.span.clone()));
+                        }
+                        Some(TokenType::LabelReference) => diagnostics.push(Diagnostic::error(
+                            format!(
+                                "Label addresses are 32 bit, ✉{} can't be used after 📏{}",
+                                token.value, width
+                            ),
+                            Some(token.span.clone()),
+                        )),
+                        _ => diagnostics.push(Diagnostic::error(
+                            format!("Unsupported token in data section: {}", token),
+                            Some(token.span.clone()),
+                        )),
                     }
                 }
 
                 sections.push(DataSection {
                     name: section_name.clone(),
                     bytes: section_data,
+                    read_only: tokens[0].t == Some(TokenType::ReadOnlySection),
+                    relocations: section_relocations,
+                });
+
+                continue;
+            }
+            Some(TokenType::Reservation) => {
+                let name = &tokens[0].value;
+                if let Some(first_span) = section_spans.get(name) {
+                    let diagnostic = if sections.iter().any(|s| &s.name == name) {
+                        Diagnostic::error(
+                            format!("Reservation {} has the same name as a data section", name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note(
+                            "data section defined here".to_string(),
+                            Some(first_span.clone()),
+                        )
+                    } else {
+                        Diagnostic::error(
+                            format!("Reservation {} is defined more than once", name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note("first defined here".to_string(), Some(first_span.clone()))
+                    };
+                    diagnostics.push(diagnostic);
+                    continue;
+                }
+                if let Some(label_span) = label_spans.get(name) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            format!("Reservation {} has the same name as a label", name),
+                            Some(tokens[0].span.clone()),
+                        )
+                        .with_note("label defined here".to_string(), Some(label_span.clone())),
+                    );
+                }
+                section_spans.insert(name.clone(), tokens[0].span.clone());
+
+                // Reservations are referenced just like sections.
+                let reference = Token {
+                    t: Some(TokenType::SectionReference),
+                    value: name.clone(),
+                    span: tokens[0].span.clone(),
+                };
+                constants.insert(name.clone(), reference);
+
+                let size = match tokens.get(1) {
+                    Some(token) if token.t == Some(TokenType::Memory) => {
+                        match parse_number(&token.value) {
+                            // An empty reservation would have no address
+                            // in an executable without .bss.
+                            Ok(size) if size > 0 => size as u32,
+                            _ => {
+                                diagnostics.push(Diagnostic::error(
+                                    format!("Invalid reservation size {}", token.value),
+                                    Some(token.span.clone()),
+                                ));
+                                continue;
+                            }
+                        }
+                    }
+                    Some(token) => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("Unsupported token in reservation: {}", token),
+                            Some(token.span.clone()),
+                        ));
+                        continue;
+                    }
+                    None => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("Reservation {} is missing a size", name),
+                            Some(tokens[0].span.clone()),
+                        ));
+                        continue;
+                    }
+                };
+                if let Some(token) = tokens.get(2) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("Unsupported token in reservation: {}", token),
+                        Some(token.span.clone()),
+                    ));
+                    continue;
+                }
+
+                reservations.push(Reservation {
+                    name: name.clone(),
+                    size,
                 });
 
                 continue;
@@ -129,151 +509,463 @@ fn process(filename: &str) -> Result<Vec<DataSection>, Box<dyn error::Error>> {
         };
 
         // Replace ConstantReferences.
-        tokens = tokens
+        let mut unknown_constant = false;
+        let tokens: Vec<Token> = tokens
             .into_iter()
             .map(|token| match token.t {
                 Some(TokenType::ConstantReference) => match constants.get(&token.value) {
-                    Some(token) => token.clone(),
-                    _ => panic!("ConstantReference {} not found", token.value),
+                    // Keep pointing at the reference, not the definition.
+                    Some(constant) => Token {
+                        span: token.span,
+                        ..constant.clone()
+                    },
+                    None => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("Unknown constant {}", token.value),
+                            Some(token.span.clone()),
+                        ));
+                        unknown_constant = true;
+                        token
+                    }
                 },
                 _ => token,
             })
             .collect();
 
-        let intermediate_instruction = compile(tokens)?;
-        let mut padded_intermediate_instruction = vec![];
-        let mut displacements = vec![];
-        for intermediate in intermediate_instruction {
-            padded_intermediate_instruction.push(intermediate.clone());
+        if unknown_constant {
+            continue;
+        }
 
-            if let IntermediateCode::Displacement32(_) = intermediate {
-                displacements.push(padded_intermediate_instruction.len() - 1);
-                padded_intermediate_instruction.append(&mut vec![IntermediateCode::Padding; 3]);
+        if tokens[0].t == Some(TokenType::Call) {
+            if let Some(target) = tokens.get(1) {
+                if target.t == Some(TokenType::LabelReference) {
+                    functions.insert(target.value.clone());
+                }
             }
         }
 
-        for displacement in displacements {
-            intermediate_index_instruction_offset.insert(
-                intermediate_program.len() + displacement,
-                padded_intermediate_instruction.len() - displacement,
+        let tokens_span = span_of(&tokens);
+        let label_references: HashMap<String, Span> = tokens
+            .iter()
+            .filter(|token| token.t == Some(TokenType::LabelReference))
+            .map(|token| (token.value.clone(), token.span.clone()))
+            .collect();
+        let intermediate_instruction = match compile(tokens) {
+            Ok(intermediate_instruction) => intermediate_instruction,
+            Err(e) => {
+                diagnostics.push(to_diagnostic(e, tokens_span));
+                continue;
+            }
+        };
+        let start = intermediate_program.len();
+        let end = start + intermediate_instruction.len();
+        for (i, intermediate) in intermediate_instruction.iter().enumerate() {
+            let reference = match intermediate {
+                IntermediateCode::Displacement32(label)
+                | IntermediateCode::Branch { label, .. } => {
+                    instruction_ends.insert(start + i, end);
+                    label
+                }
+                IntermediateCode::Absolute32(symbol) => symbol,
+                IntermediateCode::Byte(_) => continue,
+            };
+            reference_spans.insert(
+                start + i,
+                label_references
+                    .get(reference)
+                    .cloned()
+                    .or_else(|| tokens_span.clone()),
             );
         }
 
-        intermediate_program.append(&mut padded_intermediate_instruction);
+        instruction_lines.push((start, line_span));
+        intermediate_program.extend(intermediate_instruction);
     }
 
+    // Start with short branches to all known labels and make the ones
+    // that don't reach their label near. This moves everything after
+    // them, which can put other branches out of range, so it's
+    // repeated until nothing changes. Branches only ever grow so this
+    // always ends.
+    let mut near_branches: HashSet<usize> = intermediate_program
+        .iter()
+        .enumerate()
+        .filter_map(|(i, intermediate)| match intermediate {
+            IntermediateCode::Branch { label, .. } if !labels.contains_key(label) => Some(i),
+            _ => None,
+        })
+        .collect();
+    let offsets = loop {
+        let offsets = layout(&intermediate_program, &near_branches);
+        let out_of_range: Vec<usize> = intermediate_program
+            .iter()
+            .enumerate()
+            .filter_map(|(i, intermediate)| match intermediate {
+                IntermediateCode::Branch { label, .. } if !near_branches.contains(&i) => {
+                    let displacement =
+                        offsets[labels[label]] as i32 - offsets[instruction_ends[&i]] as i32;
+                    if i32::from(displacement as i8) == displacement {
+                        None
+                    } else {
+                        Some(i)
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        if out_of_range.is_empty() {
+            break offsets;
+        }
+        near_branches.extend(out_of_range);
+    };
+
     // This contains the compiled program. It is the intermediate
     // program with all the intermediate symbols translated to bytes.
     let mut program: Vec<u8> = vec![];
+    let mut relocations = vec![];
     for (i, intermediate) in intermediate_program.iter().enumerate() {
         let mut bytes = match intermediate {
             IntermediateCode::Byte(b) => vec![*b],
-            IntermediateCode::Displacement32(s) => match labels.get(s) {
-                Some(target_i) => {
-                    let instruction_end =
-                        i as i32 + intermediate_index_instruction_offset[&i] as i32;
-                    let displacement = *target_i as i32 - instruction_end;
-                    let mut v = Vec::new();
-                    v.extend_from_slice(&displacement.to_le_bytes());
-                    v
+            IntermediateCode::Displacement32(s) => {
+                let instruction_end = offsets[instruction_ends[&i]] as i32;
+                match labels.get(s) {
+                    Some(target_i) => {
+                        let displacement = offsets[*target_i] as i32 - instruction_end;
+                        displacement.to_le_bytes().to_vec()
+                    }
+                    // Labels from other object files are resolved by
+                    // the linker.
+                    None if config.emit == Emit::Object => {
+                        relocations.push(Relocation {
+                            offset: offsets[i],
+                            symbol: s.clone(),
+                            addend: offsets[i] as i32 - instruction_end,
+                            relative: true,
+                        });
+                        vec![0; 4]
+                    }
+                    None => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("Unknown label {}", s),
+                            reference_spans[&i].clone(),
+                        ));
+                        vec![]
+                    }
                 }
-                None => panic!("Unknown label {}", s),
-            },
-            IntermediateCode::Padding => vec![],
+            }
+            IntermediateCode::Branch {
+                short_opcode,
+                near_opcode,
+                label,
+            } => {
+                let instruction_end = offsets[instruction_ends[&i]] as i32;
+                match (labels.get(label), near_opcode) {
+                    (Some(target_i), _) if !near_branches.contains(&i) => {
+                        let displacement = offsets[*target_i] as i32 - instruction_end;
+                        vec![*short_opcode, displacement as u8]
+                    }
+                    (Some(target_i), Some(near_opcode)) => {
+                        let displacement = offsets[*target_i] as i32 - instruction_end;
+                        let mut v = near_opcode.clone();
+                        v.extend_from_slice(&displacement.to_le_bytes());
+                        v
+                    }
+                    // Like above, labels from other object files are
+                    // resolved by the linker.
+                    (None, Some(near_opcode)) if config.emit == Emit::Object => {
+                        let displacement_offset = offsets[i] + near_opcode.len();
+                        relocations.push(Relocation {
+                            offset: displacement_offset,
+                            symbol: label.clone(),
+                            addend: displacement_offset as i32 - instruction_end,
+                            relative: true,
+                        });
+                        let mut v = near_opcode.clone();
+                        v.extend_from_slice(&[0; 4]);
+                        v
+                    }
+                    (None, _) if config.emit != Emit::Object => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("Unknown label {}", label),
+                            reference_spans[&i].clone(),
+                        ));
+                        vec![]
+                    }
+                    (None, _) => {
+                        diagnostics.push(Diagnostic::error(
+                            format!(
+                                "Label {} should be defined in this file, this jump can only reach 128 bytes back or 127 bytes forward",
+                                label
+                            ),
+                            reference_spans[&i].clone(),
+                        ));
+                        vec![]
+                    }
+                    _ => {
+                        diagnostics.push(Diagnostic::error(
+                            format!(
+                                "Label {} is too far away, this jump can only reach 128 bytes back or 127 bytes forward",
+                                label
+                            ),
+                            reference_spans[&i].clone(),
+                        ));
+                        vec![]
+                    }
+                }
+            }
+            // Data sections are always known at this point. Like
+            // above, labels from other object files are resolved by
+            // the linker.
+            IntermediateCode::Absolute32(s)
+                if labels.contains_key(s)
+                    || section_spans.contains_key(s)
+                    || config.emit == Emit::Object =>
+            {
+                relocations.push(Relocation {
+                    offset: offsets[i],
+                    symbol: s.clone(),
+                    addend: 0,
+                    relative: false,
+                });
+                vec![0; 4]
+            }
+            IntermediateCode::Absolute32(s) => {
+                diagnostics.push(Diagnostic::error(
+                    format!("Unknown label {}", s),
+                    reference_spans[&i].clone(),
+                ));
+                vec![]
+            }
         };
         program.append(&mut bytes);
     }
 
-    sections.push(DataSection {
-        name: CODE_SECTION_NAME.to_string(),
-        bytes: program,
-    });
-    Ok(sections)
+    if config.emit != Emit::Object {
+        for (label, span) in data_label_references {
+            if !labels.contains_key(&label) {
+                diagnostics.push(Diagnostic::error(
+                    format!("Unknown label {}", label),
+                    Some(span),
+                ));
+            }
+        }
+    }
+
+    for (label, span) in &exports {
+        if !labels.contains_key(label) {
+            diagnostics.push(Diagnostic::error(
+                format!("Unknown label {}", label),
+                Some(span.clone()),
+            ));
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        if error_limit > 0 && diagnostics.len() > error_limit {
+            diagnostics.truncate(error_limit);
+            let last = diagnostics.pop().unwrap().with_note(
+                format!(
+                    "error limit of {} reached, later errors are not reported",
+                    error_limit
+                ),
+                None,
+            );
+            diagnostics.push(last);
+        }
+
+        return Err(diagnostics);
+    }
+
+    let mut labels: Vec<(String, usize)> = labels
+        .into_iter()
+        .map(|(label, i)| (label, offsets[i]))
+        .collect();
+    labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    Ok(Program {
+        code: program,
+        data_sections: sections,
+        reservations,
+        labels,
+        functions,
+        exports: exports.into_iter().map(|(label, _)| label).collect(),
+        relocations,
+        lines: instruction_lines
+            .into_iter()
+            .map(|(i, span)| (offsets[i], span))
+            .collect(),
+    })
 }
 
-fn create_string_table(strings: &Vec<&String>) -> Vec<u8> {
-    let mut table: Vec<u8> = vec![0x00]; // first byte is defined to be null
-    for s in strings {
-        table.extend(s.bytes());
-        table.push(0x00);
+// The byte offset of every index in the intermediate program and of
+// the end of the program. Branches in near_branches use a 32 bit
+// displacement if they can, other branches an 8 bit one.
+fn layout(intermediate_program: &[IntermediateCode], near_branches: &HashSet<usize>) -> Vec<usize> {
+    let mut offsets = vec![0];
+    for (i, intermediate) in intermediate_program.iter().enumerate() {
+        let size = match intermediate {
+            IntermediateCode::Byte(_) => 1,
+            IntermediateCode::Displacement32(_) | IntermediateCode::Absolute32(_) => 4,
+            IntermediateCode::Branch {
+                near_opcode: Some(near_opcode),
+                ..
+            } if near_branches.contains(&i) => near_opcode.len() + 4,
+            IntermediateCode::Branch { .. } => 2,
+        };
+        offsets.push(offsets[i] + size);
     }
 
-    table
+    offsets
 }
 
-fn create_section_header_entry(
-    sh_name: u32,
+// A section of an executable that isn't loaded in memory.
+struct DebugSection<'a> {
+    name: &'static str,
     sh_type: u32,
-    sh_flags: u32,
-    sh_addr: u32,
-    sh_offset: u32,
-    sh_size: u32,
+    contents: &'a [u8],
     sh_link: u32,
     sh_info: u32,
     sh_addralign: u32,
     sh_entsize: u32,
-) -> Vec<u8> {
-    let mut entry: Vec<u8> = vec![];
-    // typedef struct
-    // {
-    //     Elf32_Word    sh_name;                /* Section name (string tbl index) */
-    //     Elf32_Word    sh_type;                /* Section type */
-    //     Elf32_Word    sh_flags;               /* Section flags */
-    //     Elf32_Addr    sh_addr;                /* Section virtual addr at execution */
-    //     Elf32_Off     sh_offset;              /* Section file offset */
-    //     Elf32_Word    sh_size;                /* Section size in bytes */
-    //     Elf32_Word    sh_link;                /* Link to another section */
-    //     Elf32_Word    sh_info;                /* Additional section information */
-    //     Elf32_Word    sh_addralign;           /* Section alignment */
-    //     Elf32_Word    sh_entsize;             /* Entry size if section holds table */
-    // } Elf32_Shdr;
-
-    // sh_name
-    entry.extend_from_slice(&sh_name.to_le_bytes());
-
-    // sh_type
-    entry.extend_from_slice(&sh_type.to_le_bytes());
-
-    // sh_flags
-    entry.extend_from_slice(&sh_flags.to_le_bytes());
-
-    // sh_addr
-    entry.extend_from_slice(&sh_addr.to_le_bytes());
+}
 
-    // sh_offset
-    entry.extend_from_slice(&sh_offset.to_le_bytes());
+const DEBUG_SECTION_NAMES: [&str; 5] = [
+    SYMTAB_SECTION_NAME,
+    SYMBOL_STRTAB_SECTION_NAME,
+    DEBUG_LINE_SECTION_NAME,
+    DEBUG_INFO_SECTION_NAME,
+    DEBUG_ABBREV_SECTION_NAME,
+];
+
+// Sections of an executable that are left out when it's stripped:
+// the symbol table and debug information. They are placed after the
+// code, one after the other in the order of sections().
+struct DebugSections {
+    offset: u32,
+    symbols: Vec<u8>,
+    names: StringTable,
+    debug_line: Vec<u8>,
+    debug_info: Vec<u8>,
+    debug_abbrev: Vec<u8>,
+}
 
-    // sh_size
-    entry.extend_from_slice(&sh_size.to_le_bytes());
+impl DebugSections {
+    // symtab_index is the section index of the first section,
+    // .symtab.
+    fn sections(&self, symtab_index: u32) -> Vec<DebugSection<'_>> {
+        vec![
+            DebugSection {
+                name: DEBUG_SECTION_NAMES[0],
+                sh_type: SHT_SYMTAB,
+                contents: &self.symbols,
+                sh_link: symtab_index + 1, // .strtab
+                sh_info: 0x01,             // all symbols except the first are global
+                sh_addralign: 0x04,
+                sh_entsize: SYMBOL_TABLE_ENTRY_SIZE,
+            },
+            DebugSection {
+                name: DEBUG_SECTION_NAMES[1],
+                sh_type: SHT_STRTAB,
+                contents: &self.names.bytes,
+                sh_link: 0x00,
+                sh_info: 0x00,
+                sh_addralign: 0x01,
+                sh_entsize: 0x00,
+            },
+            DebugSection {
+                name: DEBUG_SECTION_NAMES[2],
+                sh_type: SHT_PROGBITS,
+                contents: &self.debug_line,
+                sh_link: 0x00,
+                sh_info: 0x00,
+                sh_addralign: 0x01,
+                sh_entsize: 0x00,
+            },
+            DebugSection {
+                name: DEBUG_SECTION_NAMES[3],
+                sh_type: SHT_PROGBITS,
+                contents: &self.debug_info,
+                sh_link: 0x00,
+                sh_info: 0x00,
+                sh_addralign: 0x01,
+                sh_entsize: 0x00,
+            },
+            DebugSection {
+                name: DEBUG_SECTION_NAMES[4],
+                sh_type: SHT_PROGBITS,
+                contents: &self.debug_abbrev,
+                sh_link: 0x00,
+                sh_info: 0x00,
+                sh_addralign: 0x01,
+                sh_entsize: 0x00,
+            },
+        ]
+    }
+}
 
-    // sh_link
-    entry.extend_from_slice(&sh_link.to_le_bytes());
+// A part of an executable that is loaded in memory. Each one gets its
+// own section and PT_LOAD segment.
+struct Segment {
+    name: String,
+    sh_type: u32,
+    sh_flags: u32,
+    p_flags: u32,
+    bytes: Vec<u8>,
+    // At least bytes.len(), the rest is zeroed when loaded.
+    memory_size: u32,
+}
 
-    // sh_info
-    entry.extend_from_slice(&sh_info.to_le_bytes());
+// p_flags
+const PF_X: u32 = 1;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
+
+// Where the parts of an executable are placed in the file. Each
+// segment starts on a new page. The headers and the section name
+// string table come before them.
+struct Layout {
+    string_table_offset: u32,
+    // A segment that isn't (fully) in the file, like .bss, is still
+    // given the file offset it would have so that its address can be
+    // calculated like the others.
+    segment_offsets: Vec<u32>,
+}
 
-    // sh_addralign
-    entry.extend_from_slice(&sh_addralign.to_le_bytes());
+impl Layout {
+    // The virtual address a file offset is loaded at.
+    fn address(offset: u32) -> u32 {
+        LOAD_ADDRESS + offset
+    }
+}
 
-    // sh_entsize
-    entry.extend_from_slice(&sh_entsize.to_le_bytes());
+fn create_layout(segments: &[Segment], string_table_size: u32) -> Layout {
+    let program_header_size = PROGRAM_HEADER_ENTRY_SIZE * segments.len() as u32;
+    let string_table_offset =
+        STRTABLE_PHYSICAL_ENTRY_POINT.max(ELF_HEADER_SIZE + program_header_size);
+
+    let mut offset =
+        DATA_SECTION_PHYSICAL_START.max(align(string_table_offset + string_table_size, PAGE_SIZE));
+    let mut segment_offsets = vec![];
+    for segment in segments {
+        segment_offsets.push(offset);
+        // Empty segments still get their own page so they have a
+        // unique address.
+        offset += align(segment.memory_size.max(1), PAGE_SIZE);
+    }
 
-    entry
+    Layout {
+        string_table_offset,
+        segment_offsets,
+    }
 }
 
 fn create_section_header(
-    program_size: u32,
-    data_section_sizes: &[u32],
-    data_section_names: &[&String],
+    layout: &Layout,
+    segments: &[Segment],
+    debug_sections: Option<&DebugSections>,
     strtable_size: u32,
 ) -> Vec<u8> {
-    const SHT_NULL: u32 = 0x00;
-    const SHT_PROGBITS: u32 = 0x01;
-    const SHT_STRTAB: u32 = 0x03;
-    const SHF_WRITE: u32 = 0x01;
-    const SHF_ALLOC: u32 = 0x02;
-    const SHF_EXECINSTR: u32 = 0x04;
-
     let mut section_header: Vec<u8> = vec![];
     let mut strtab_index = 0x01;
 
@@ -282,49 +974,52 @@ fn create_section_header(
         0x00, SHT_NULL, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ));
 
-    let mut next_section_virtual_start = DATA_SECTION_VIRTUAL_START;
-    let mut next_section_physical_start = DATA_SECTION_PHYSICAL_START;
-    for (index, size) in data_section_sizes.iter().enumerate() {
+    for (segment, offset) in segments.iter().zip(&layout.segment_offsets) {
         section_header.append(&mut create_section_header_entry(
             strtab_index,
-            SHT_PROGBITS,
-            SHF_WRITE | SHF_ALLOC,
-            next_section_virtual_start,
-            next_section_physical_start,
-            *size,
+            segment.sh_type,
+            segment.sh_flags,
+            Layout::address(*offset),
+            *offset,
+            segment.memory_size,
             0x00,
             0x00,
             0x01, // (no alignment constraint)
             0x00,
         ));
 
-        // TODO program sizes are assumed to be 4KB
-        next_section_physical_start += PAGE_SIZE;
-        next_section_virtual_start += PAGE_SIZE;
-        strtab_index += data_section_names[index].len() as u32 + 1;
+        strtab_index += segment.name.len() as u32 + 1;
     }
 
-    // executable code
-    section_header.append(&mut create_section_header_entry(
-        strtab_index,
-        SHT_PROGBITS,
-        SHF_ALLOC | SHF_EXECINSTR,
-        next_section_virtual_start,
-        next_section_physical_start,
-        program_size,
-        0x00,
-        0x00,
-        0x01, // (no alignment constraint)
-        0x00,
-    ));
+    if let Some(debug_sections) = debug_sections {
+        // .symtab comes right after the segments
+        let symtab_index = segments.len() as u32 + 1;
+        let mut offset = debug_sections.offset;
+        for section in debug_sections.sections(symtab_index) {
+            section_header.append(&mut create_section_header_entry(
+                strtab_index,
+                section.sh_type,
+                0x00,
+                0x00,
+                offset,
+                section.contents.len() as u32,
+                section.sh_link,
+                section.sh_info,
+                section.sh_addralign,
+                section.sh_entsize,
+            ));
+            offset += section.contents.len() as u32;
+            strtab_index += section.name.len() as u32 + 1;
+        }
+    }
 
     // string table
     section_header.append(&mut create_section_header_entry(
-        strtab_index + (CODE_SECTION_NAME.len() + 1) as u32,
+        strtab_index,
         SHT_STRTAB,
         0x00,
         0x00,
-        STRTABLE_PHYSICAL_ENTRY_POINT,
+        layout.string_table_offset,
         strtable_size,
         0x00,
         0x00,
@@ -336,7 +1031,8 @@ fn create_section_header(
 }
 
 fn create_program_header_entry(
-    size: u32,
+    file_size: u32,
+    memory_size: u32,
     offset: u32,
     virtual_address: u32,
     flags: u32,
@@ -371,219 +1067,1712 @@ fn create_program_header_entry(
     entry.extend_from_slice(&virtual_address.to_le_bytes());
 
     // p_filesz
-    entry.extend_from_slice(&size.to_le_bytes());
+    entry.extend_from_slice(&file_size.to_le_bytes());
 
-    // p_memsz
-    entry.extend_from_slice(&size.to_le_bytes());
+    // p_memsz (the part that isn't in the file is zeroed)
+    entry.extend_from_slice(&memory_size.to_le_bytes());
 
     // p_flags
     entry.extend_from_slice(&flags.to_le_bytes());
 
     // p_align
     // align on 4KB
-    entry.extend_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+    entry.extend_from_slice(&PAGE_SIZE.to_le_bytes());
 
     entry
 }
 
-fn create_program_header(program_size: u32, data_section_sizes: &Vec<u32>) -> Vec<u8> {
-    const PF_X_R: u32 = 1 | (1 << 2);
-    let mut program_header = create_program_header_entry(
-        program_size,
-        DATA_SECTION_PHYSICAL_START + PAGE_SIZE * data_section_sizes.len() as u32, // TODO this assumes data sections are 4KB
-        DATA_SECTION_VIRTUAL_START + PAGE_SIZE * data_section_sizes.len() as u32, // TODO this assumes data sections are 4KB
-        PF_X_R,
-    );
-
-    let mut physical_address = DATA_SECTION_PHYSICAL_START;
-    let mut virtual_address = DATA_SECTION_VIRTUAL_START;
-    const PF_R_W: u32 = (1 << 2) | (1 << 1);
-    for size in data_section_sizes.iter() {
+fn create_program_header(layout: &Layout, segments: &[Segment]) -> Vec<u8> {
+    let mut program_header = vec![];
+    for (segment, offset) in segments.iter().zip(&layout.segment_offsets) {
         program_header.append(&mut create_program_header_entry(
-            *size,
-            physical_address,
-            virtual_address,
-            PF_R_W,
+            segment.bytes.len() as u32,
+            segment.memory_size,
+            *offset,
+            Layout::address(*offset),
+            segment.p_flags,
         ));
-
-        // TODO program sizes are assumed to be 4KB
-        physical_address += PAGE_SIZE;
-        virtual_address += PAGE_SIZE;
     }
 
     program_header
 }
 
-fn create_elf_header(number_of_program_headers: u32, number_of_sections: u32) -> Vec<u8> {
-    const END_ELF_HEADER: u32 = 0x34;
-    const PROGRAM_HEADER_SIZE: u32 = 32;
-    let mut header: Vec<u8> = vec![];
+// Fills in the bytes described by relocation. symbol_address is the
+// address of the symbol it refers to and code_address the address
+// bytes will be loaded at, which only matters for relative
+// relocations.
+fn apply_relocation(
+    bytes: &mut [u8],
+    relocation: &Relocation,
+    symbol_address: u32,
+    code_address: u32,
+) {
+    let mut value = symbol_address.wrapping_add(relocation.addend as u32);
+    if relocation.relative {
+        value = value.wrapping_sub(code_address + relocation.offset as u32);
+    }
 
-    // Magic number
-    header.append(&mut vec![0x7f, 0x45, 0x4c, 0x46]);
+    bytes[relocation.offset..relocation.offset + 4].copy_from_slice(&value.to_le_bytes());
+}
 
-    // 32 bit
-    header.push(0x01);
+// A symbol of an executable, placed at offset in segment number
+// segment.
+struct Symbol<'a> {
+    name: &'a str,
+    segment: usize,
+    offset: u32,
+    size: u32,
+    symbol_type: u8,
+}
 
-    // little endian
-    header.push(0x01);
+impl<'a> Symbol<'a> {
+    fn address(&self, layout: &Layout) -> u32 {
+        Layout::address(layout.segment_offsets[self.segment]) + self.offset
+    }
+}
 
-    // ELF version 1
-    header.push(0x01);
+// Creates the symbol table of an executable.
+fn create_symbol_table(symbols: &[Symbol], layout: &Layout) -> (Vec<u8>, StringTable) {
+    let mut names = StringTable::new();
+    let mut symbol_table = create_symbol_table_entry(0, 0, 0, STB_LOCAL, STT_NOTYPE, SHN_UNDEF);
+
+    for symbol in symbols {
+        symbol_table.extend(create_symbol_table_entry(
+            names.add(symbol.name),
+            symbol.address(layout),
+            symbol.size,
+            STB_GLOBAL,
+            symbol.symbol_type,
+            symbol.segment as u16 + 1,
+        ));
+    }
 
-    // Target operation system ABI (System V)
-    header.push(0x00);
+    (symbol_table, names)
+}
 
-    // ABI version (currently unused)
-    header.push(0x00);
+// Creates the symbol table and debug information of an executable
+// whose code is loaded at code_address. offset is where they will be
+// placed in the file.
+fn create_debug_sections(
+    program: &Program,
+    symbols: &[Symbol],
+    layout: &Layout,
+    code_address: u32,
+    offset: u32,
+) -> DebugSections {
+    let (symbols, names) = create_symbol_table(symbols, layout);
+
+    let mut files: Vec<&str> = vec![];
+    let mut rows = vec![];
+    for (code_offset, span) in &program.lines {
+        let file = match files.iter().position(|file| *file == span.file) {
+            Some(file) => file,
+            None => {
+                files.push(&span.file);
+                files.len() - 1
+            }
+        };
+        rows.push(Row {
+            address: code_address + *code_offset as u32,
+            file,
+            line: span.line,
+        });
+    }
 
-    // EIPAD (currently unused)
-    header.append(&mut vec![0x00; 7]);
+    let code_end = code_address + program.code.len() as u32;
+    let comp_dir = env::current_dir()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    DebugSections {
+        offset,
+        symbols,
+        names,
+        debug_line: create_debug_line(&files, &rows, code_end),
+        debug_info: create_debug_info(
+            files.first().unwrap_or(&""),
+            &comp_dir,
+            code_address,
+            code_end,
+        ),
+        debug_abbrev: create_debug_abbrev(),
+    }
+}
 
-    // Object file type (ET_EXEC)
-    header.append(&mut vec![0x02, 0x00]);
+// Splits program in the segments of an executable:
+//
+// - a writable segment for every 📗 data section
+// - .rodata, all read-only 📕 data sections (if there are any)
+// - .code
+// - .bss, all reservations (if there are any)
+//
+// Also returns where its symbols are placed and the index of the code
+// segment.
+fn create_segments(program: &Program) -> (Vec<Segment>, Vec<Symbol<'_>>, usize) {
+    let mut segments = vec![];
+    let mut symbols = vec![];
+
+    let mut rodata = vec![];
+    let mut rodata_symbols = vec![];
+    for section in &program.data_sections {
+        let mut symbol = Symbol {
+            name: &section.name,
+            segment: segments.len(),
+            offset: 0,
+            size: section.bytes.len() as u32,
+            symbol_type: STT_OBJECT,
+        };
 
-    // Target architecture x86
-    header.append(&mut vec![0x03, 0x00]);
+        if section.read_only {
+            rodata.resize(align(rodata.len() as u32, 4) as usize, 0);
+            symbol.offset = rodata.len() as u32;
+            rodata.extend(&section.bytes);
+            rodata_symbols.push(symbols.len());
+        } else {
+            segments.push(Segment {
+                name: section.name.clone(),
+                sh_type: SHT_PROGBITS,
+                sh_flags: SHF_WRITE | SHF_ALLOC,
+                p_flags: PF_R | PF_W,
+                bytes: section.bytes.clone(),
+                memory_size: section.bytes.len() as u32,
+            });
+        }
+        symbols.push(symbol);
+    }
 
-    // ELF version 1
-    header.extend_from_slice(&(1 as u32).to_le_bytes());
+    if !rodata.is_empty() {
+        for symbol in rodata_symbols {
+            symbols[symbol].segment = segments.len();
+        }
+        segments.push(Segment {
+            name: RODATA_SECTION_NAME.to_string(),
+            sh_type: SHT_PROGBITS,
+            sh_flags: SHF_ALLOC,
+            p_flags: PF_R,
+            memory_size: rodata.len() as u32,
+            bytes: rodata,
+        });
+    }
 
-    // e_entry
-    // TODO this assumes 4 KB data sections
-    // -3 because string table appears in the first page and null delimiter
-    // and code don't offset the virtual entry point
-    header.extend_from_slice(
-        &(DATA_SECTION_VIRTUAL_START + (number_of_sections - 3) * PAGE_SIZE).to_le_bytes(),
-    );
+    let code_segment = segments.len();
+    segments.push(Segment {
+        name: CODE_SECTION_NAME.to_string(),
+        sh_type: SHT_PROGBITS,
+        sh_flags: SHF_ALLOC | SHF_EXECINSTR,
+        p_flags: PF_R | PF_X,
+        bytes: program.code.clone(),
+        memory_size: program.code.len() as u32,
+    });
+    for (label, offset) in &program.labels {
+        let symbol_type = if program.functions.contains(label) {
+            STT_FUNC
+        } else {
+            STT_NOTYPE
+        };
+        symbols.push(Symbol {
+            name: label,
+            segment: code_segment,
+            offset: *offset as u32,
+            size: 0,
+            symbol_type,
+        });
+    }
 
-    // Start of program header table (immediately after this header)
-    header.extend_from_slice(&END_ELF_HEADER.to_le_bytes());
+    let (reservation_offsets, bss_size) = reservation_offsets(&program.reservations);
+    if bss_size > 0 {
+        for (reservation, offset) in program.reservations.iter().zip(reservation_offsets) {
+            symbols.push(Symbol {
+                name: &reservation.name,
+                segment: segments.len(),
+                offset,
+                size: reservation.size,
+                symbol_type: STT_OBJECT,
+            });
+        }
+        segments.push(Segment {
+            name: BSS_SECTION_NAME.to_string(),
+            sh_type: SHT_NOBITS,
+            sh_flags: SHF_WRITE | SHF_ALLOC,
+            p_flags: PF_R | PF_W,
+            bytes: vec![],
+            memory_size: bss_size,
+        });
+    }
 
-    // e_shoff: Start of section header table
-    let program_header_table_size: u32 = number_of_program_headers * PROGRAM_HEADER_SIZE;
-    header.extend_from_slice(&(END_ELF_HEADER + program_header_table_size).to_le_bytes());
+    (segments, symbols, code_segment)
+}
 
-    // eflags
-    header.append(&mut vec![0x00; 4]);
+// Creates a statically linked executable with the segments of
+// create_segments laid out by create_layout, followed by the symbol
+// table and debug information unless strip is set.
+fn create_executable(program: &Program, strip: bool) -> Vec<u8> {
+    let (mut segments, symbols, code_segment) = create_segments(program);
+    let segment_names: Vec<&String> = segments.iter().map(|segment| &segment.name).collect();
+    let mut string_table = create_string_table(&segment_names);
+
+    // add str name for the debug sections and strtab at end of table
+    if !strip {
+        for name in DEBUG_SECTION_NAMES.iter() {
+            string_table.extend(name.bytes());
+            string_table.push(0x00);
+        }
+    }
+    string_table.extend(STRTAB_SECTION_NAME.bytes());
+    string_table.push(0x00);
 
-    // Size of this header
-    header.append(&mut vec![52, 0x00]);
+    let layout = create_layout(&segments, string_table.len() as u32);
+    let code_offset = layout.segment_offsets[code_segment];
+    let code_address = Layout::address(code_offset);
+    let symbol_addresses: HashMap<&str, u32> = symbols
+        .iter()
+        .map(|symbol| (symbol.name, symbol.address(&layout)))
+        .collect();
 
-    // e_phentsize: size of a program header table entry
-    header.append(&mut vec![PROGRAM_HEADER_SIZE as u8, 0x00]);
+    for relocation in &program.relocations {
+        let symbol_address = symbol_addresses[relocation.symbol.as_str()];
+        apply_relocation(
+            &mut segments[code_segment].bytes,
+            relocation,
+            symbol_address,
+            code_address,
+        );
+    }
 
-    // e_phnum: number of entries in program header table
-    header.append(&mut vec![number_of_program_headers as u8, 0x00]);
+    // Data sections only contain absolute addresses, so where they
+    // are loaded doesn't matter.
+    for section in &program.data_sections {
+        let symbol = symbols
+            .iter()
+            .find(|symbol| symbol.name == section.name)
+            .unwrap();
+        for relocation in &section.relocations {
+            apply_relocation(
+                &mut segments[symbol.segment].bytes[symbol.offset as usize..],
+                relocation,
+                symbol_addresses[relocation.symbol.as_str()],
+                0,
+            );
+        }
+    }
 
-    // e_shentsize: size of a section header table entry
-    header.append(&mut vec![40, 0x00]);
+    let program_header = create_program_header(&layout, &segments);
+
+    let debug_sections = if strip {
+        None
+    } else {
+        let offset = align(code_offset + program.code.len() as u32, 4);
+        Some(create_debug_sections(
+            program,
+            &symbols,
+            &layout,
+            code_address,
+            offset,
+        ))
+    };
 
-    // e_shnum: number of entries in section header table
-    header.append(&mut vec![number_of_sections as u8, 0x00]);
+    let section_header = create_section_header(
+        &layout,
+        &segments,
+        debug_sections.as_ref(),
+        string_table.len() as u32,
+    );
 
-    // e_shstrndx: index of section header table entry that contains section names
-    header.append(&mut vec![(number_of_sections - 1) as u8, 0x00]);
+    // The ELF header is written last because the section header
+    // table is placed at the end of the file.
+    let mut elf = vec![0; ELF_HEADER_SIZE as usize];
+    elf.extend(program_header);
 
-    header
-}
+    elf.resize(layout.string_table_offset as usize, 0);
+    elf.extend(string_table);
 
-#[cfg(test)]
-mod test_elf {
-    use super::*;
+    for (segment, offset) in segments.iter().zip(&layout.segment_offsets) {
+        if !segment.bytes.is_empty() {
+            elf.resize(*offset as usize, 0);
+            elf.extend(&segment.bytes);
+        }
+    }
 
-    #[test]
-    fn test_elf_header_length() {
-        assert_eq!(create_elf_header(1, 3).len(), 52);
+    if let Some(debug_sections) = debug_sections {
+        elf.resize(debug_sections.offset as usize, 0);
+        for section in debug_sections.sections(0) {
+            elf.extend(section.contents);
+        }
     }
 
-    #[test]
-    fn test_section_header_length() {
-        const BYTES_PER_FIELD: usize = 4;
-        const FIELDS_PER_ENTRY: usize = 10;
-        const ENTRIES: usize = 3;
+    let section_header_offset = align(elf.len() as u32, 4);
+    elf.resize(section_header_offset as usize, 0);
+    elf.extend(&section_header);
 
-        assert_eq!(
-            create_section_header(0, &vec![], &vec![], 0).len(),
-            BYTES_PER_FIELD * FIELDS_PER_ENTRY * ENTRIES
-        );
-    }
+    let elf_header = create_elf_header(
+        ET_EXEC,
+        code_address,
+        segments.len() as u32,
+        section_header_offset,
+        section_header.len() as u32 / SECTION_HEADER_ENTRY_SIZE,
+    );
+    elf[..ELF_HEADER_SIZE as usize].copy_from_slice(&elf_header);
+    elf
+}
 
-    #[test]
-    fn test_program_header_length() {
-        assert_eq!(create_program_header(0, &vec![]).len(), 8 * 4);
+// Creates a relocatable object file that can be linked by ld. It has
+// the following sections:
+//
+// [0]  null sentinel
+// [1]  .text        the code
+// [2]  .data        all data sections, one after the other
+// [3]  .rodata      all read-only data sections
+// [4]  .bss         all reservations
+// [5]  .symtab      a global symbol for every label and undefined
+//                   label reference
+// [6]  .strtab      names of the symbols
+// [7]  .rel.text    references to data sections, reservations and
+//                   labels in the code
+// [8]  .rel.data    label addresses in .data
+// [9]  .rel.rodata  label addresses in .rodata
+// [10] .shstrtab    names of the sections
+fn create_object(program: &Program) -> Vec<u8> {
+    const TEXT_INDEX: u16 = 1;
+    const DATA_INDEX: u16 = 2;
+    const RODATA_INDEX: u16 = 3;
+    const BSS_INDEX: u16 = 4;
+    const SYMTAB_INDEX: u32 = 5;
+    const STRTAB_INDEX: u32 = 6;
+    const NUMBER_OF_SECTIONS: u32 = 11;
+    const DATA_SYMBOL_INDEX: u32 = 2;
+    const RODATA_SYMBOL_INDEX: u32 = 3;
+    const BSS_SYMBOL_INDEX: u32 = 4;
+
+    // Data sections and reservations are referred to through the
+    // section symbol of .data, .rodata and .bss. This maps their name
+    // to that symbol and their offset in the section.
+    let mut section_offsets: HashMap<&str, (u32, u32)> = HashMap::new();
+    let mut data = vec![];
+    let mut rodata = vec![];
+    for section in &program.data_sections {
+        if section.read_only {
+            rodata.resize(align(rodata.len() as u32, 4) as usize, 0);
+            section_offsets.insert(&section.name, (RODATA_SYMBOL_INDEX, rodata.len() as u32));
+            rodata.extend(&section.bytes);
+        } else {
+            data.resize(align(data.len() as u32, 4) as usize, 0);
+            section_offsets.insert(&section.name, (DATA_SYMBOL_INDEX, data.len() as u32));
+            data.extend(&section.bytes);
+        }
+    }
+    let (reservation_offsets, bss_size) = reservation_offsets(&program.reservations);
+    for (reservation, offset) in program.reservations.iter().zip(reservation_offsets) {
+        section_offsets.insert(&reservation.name, (BSS_SYMBOL_INDEX, offset));
     }
-}
 
-pub fn run(config: Config) -> std::io::Result<()> {
-    println!("compile {}", config.filename);
+    // Local symbols have to come before global ones.
+    let mut strtab = StringTable::new();
+    let mut symtab = create_symbol_table_entry(0, 0, 0, STB_LOCAL, STT_NOTYPE, SHN_UNDEF);
+    symtab.extend(create_symbol_table_entry(
+        0,
+        0,
+        0,
+        STB_LOCAL,
+        STT_SECTION,
+        TEXT_INDEX,
+    ));
+    symtab.extend(create_symbol_table_entry(
+        0,
+        0,
+        0,
+        STB_LOCAL,
+        STT_SECTION,
+        DATA_INDEX,
+    ));
+    symtab.extend(create_symbol_table_entry(
+        0,
+        0,
+        0,
+        STB_LOCAL,
+        STT_SECTION,
+        RODATA_INDEX,
+    ));
+    symtab.extend(create_symbol_table_entry(
+        0,
+        0,
+        0,
+        STB_LOCAL,
+        STT_SECTION,
+        BSS_INDEX,
+    ));
 
-    let mut data_sections = process(&config.filename).unwrap();
+    // Only _start and exported labels are visible to other object
+    // files. That way two objects can both have e.g. a loop label.
+    let is_global = |label: &str| label == "_start" || program.exports.contains(label);
+    let (global_labels, local_labels): (Vec<_>, Vec<_>) = program
+        .labels
+        .iter()
+        .partition(|(label, _)| is_global(label));
+
+    let first_global_symbol = 5 + local_labels.len() as u32;
 
-    // + 2 for string table and null sentinel
-    let elf_header = create_elf_header(data_sections.len() as u32, data_sections.len() as u32 + 2);
-    let total_sections = data_sections.len();
-    let program = data_sections.remove(total_sections - 1).bytes;
+    let mut symbol_indices: HashMap<&str, u32> = HashMap::new();
+    let mut next_symbol_index = 5;
+    for (label, offset) in local_labels.into_iter().chain(global_labels) {
+        let binding = if is_global(label) {
+            STB_GLOBAL
+        } else {
+            STB_LOCAL
+        };
+        let symbol_type = if program.functions.contains(label) {
+            STT_FUNC
+        } else {
+            STT_NOTYPE
+        };
+        symtab.extend(create_symbol_table_entry(
+            strtab.add(label),
+            *offset as u32,
+            0,
+            binding,
+            symbol_type,
+            TEXT_INDEX,
+        ));
+        symbol_indices.insert(label, next_symbol_index);
+        next_symbol_index += 1;
+    }
 
-    let data_section_sizes = data_sections
+    // Relocations in .text and in the data sections, with the index
+    // of the section they're in and the offset of the relocated bytes
+    // in that section.
+    let code_relocations = program
+        .relocations
         .iter()
-        .map(|section| section.bytes.len() as u32)
-        .collect();
-    let program_header = create_program_header(program.len() as u32, &data_section_sizes);
+        .map(|relocation| (TEXT_INDEX, relocation.offset as u32, relocation));
+    let data_relocations = program.data_sections.iter().flat_map(|section| {
+        let (section_symbol, section_offset) = section_offsets[section.name.as_str()];
+        let index = if section_symbol == RODATA_SYMBOL_INDEX {
+            RODATA_INDEX
+        } else {
+            DATA_INDEX
+        };
+        section
+            .relocations
+            .iter()
+            .map(move |relocation| (index, section_offset + relocation.offset as u32, relocation))
+    });
 
-    let data_section_names = data_sections.iter().map(|section| &section.name).collect();
-    let mut string_table = create_string_table(&data_section_names);
+    let mut code = program.code.clone();
+    let mut rel_text = vec![];
+    let mut rel_data = vec![];
+    let mut rel_rodata = vec![];
+    for (index, offset, relocation) in code_relocations.chain(data_relocations) {
+        let symbol = relocation.symbol.as_str();
+        let (symbol_index, addend) = match section_offsets.get(symbol) {
+            Some((section_symbol, offset)) => (*section_symbol, *offset as i32 + relocation.addend),
+            None => {
+                if !symbol_indices.contains_key(symbol) {
+                    symtab.extend(create_symbol_table_entry(
+                        strtab.add(symbol),
+                        0,
+                        0,
+                        STB_GLOBAL,
+                        STT_NOTYPE,
+                        SHN_UNDEF,
+                    ));
+                    symbol_indices.insert(symbol, next_symbol_index);
+                    next_symbol_index += 1;
+                }
+                (symbol_indices[symbol], relocation.addend)
+            }
+        };
 
-    // add str name for code and strtab at end of table
-    string_table.extend(CODE_SECTION_NAME.bytes());
-    string_table.push(0x00);
-    string_table.extend(STRTAB_SECTION_NAME.bytes());
-    string_table.push(0x00);
+        let (bytes, rel) = match index {
+            TEXT_INDEX => (&mut code, &mut rel_text),
+            DATA_INDEX => (&mut data, &mut rel_data),
+            _ => (&mut rodata, &mut rel_rodata),
+        };
 
-    let section_header = create_section_header(
-        program.len() as u32,
-        &data_section_sizes,
-        &data_section_names,
-        string_table.len() as u32,
-    );
-    let mut file = fs::File::create("a.out")?;
-    file.set_permissions(PermissionsExt::from_mode(0o755))?;
-
-    file.write_all(&elf_header)?;
-    file.write_all(&program_header)?;
-    file.write_all(&section_header)?;
-
-    // string table starts at STRTABLE_PHYSICAL_ENTRY_POINT
-    let padding = vec![
-        0;
-        STRTABLE_PHYSICAL_ENTRY_POINT as usize
-            - elf_header.len()
-            - program_header.len()
-            - section_header.len()
-    ];
-    file.write_all(&padding)?;
-    file.write_all(&string_table)?;
-
-    let padding = vec![
-        0;
-        DATA_SECTION_PHYSICAL_START as usize
-            - STRTABLE_PHYSICAL_ENTRY_POINT as usize
-            - string_table.len()
-    ];
-    file.write_all(&padding)?;
-
-    // insert data sections
-    // DATA_SECTION_PHYSICAL_START
-    for section in data_sections.iter() {
-        let data = &section.bytes;
-        file.write_all(&data)?;
-
-        // pad current data section
-        let padding = vec![0; PAGE_SIZE as usize - (data.len() % PAGE_SIZE as usize)];
-        file.write_all(&padding)?;
-    }
-
-    file.write_all(&program)?;
+        // REL relocations store the addend in the relocated bytes.
+        let start = offset as usize;
+        bytes[start..start + 4].copy_from_slice(&addend.to_le_bytes());
+        let relocation_type = if relocation.relative {
+            R_386_PC32
+        } else {
+            R_386_32
+        };
+        rel.extend(create_relocation_entry(
+            offset,
+            symbol_index,
+            relocation_type,
+        ));
+    }
 
-    Ok(())
+    // The ELF header is written last because it contains the offset
+    // of the section header table.
+    let mut elf = vec![0; ELF_HEADER_SIZE as usize];
+    let place = |elf: &mut Vec<u8>, bytes: &[u8], alignment: u32| {
+        elf.resize(align(elf.len() as u32, alignment) as usize, 0);
+        let offset = elf.len() as u32;
+        elf.extend(bytes);
+        offset
+    };
+    let text_offset = place(&mut elf, &code, 16);
+    let data_offset = place(&mut elf, &data, 4);
+    let rodata_offset = place(&mut elf, &rodata, 4);
+    let symtab_offset = place(&mut elf, &symtab, 4);
+    let strtab_offset = place(&mut elf, &strtab.bytes, 1);
+    let rel_text_offset = place(&mut elf, &rel_text, 4);
+    let rel_data_offset = place(&mut elf, &rel_data, 4);
+    let rel_rodata_offset = place(&mut elf, &rel_rodata, 4);
+
+    let mut shstrtab = StringTable::new();
+    let text_name = shstrtab.add(TEXT_SECTION_NAME);
+    let data_name = shstrtab.add(DATA_SECTION_NAME);
+    let rodata_name = shstrtab.add(RODATA_SECTION_NAME);
+    let bss_name = shstrtab.add(BSS_SECTION_NAME);
+    let symtab_name = shstrtab.add(SYMTAB_SECTION_NAME);
+    let strtab_name = shstrtab.add(SYMBOL_STRTAB_SECTION_NAME);
+    let rel_text_name = shstrtab.add(REL_TEXT_SECTION_NAME);
+    let rel_data_name = shstrtab.add(REL_DATA_SECTION_NAME);
+    let rel_rodata_name = shstrtab.add(REL_RODATA_SECTION_NAME);
+    let shstrtab_name = shstrtab.add(STRTAB_SECTION_NAME);
+    let shstrtab_offset = place(&mut elf, &shstrtab.bytes, 1);
+
+    let mut section_header = create_section_header_entry(0, SHT_NULL, 0, 0, 0, 0, 0, 0, 0, 0);
+    section_header.extend(create_section_header_entry(
+        text_name,
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_EXECINSTR,
+        0x00,
+        text_offset,
+        code.len() as u32,
+        0x00,
+        0x00,
+        16,
+        0x00,
+    ));
+    section_header.extend(create_section_header_entry(
+        data_name,
+        SHT_PROGBITS,
+        SHF_WRITE | SHF_ALLOC,
+        0x00,
+        data_offset,
+        data.len() as u32,
+        0x00,
+        0x00,
+        4,
+        0x00,
+    ));
+    section_header.extend(create_section_header_entry(
+        rodata_name,
+        SHT_PROGBITS,
+        SHF_ALLOC,
+        0x00,
+        rodata_offset,
+        rodata.len() as u32,
+        0x00,
+        0x00,
+        4,
+        0x00,
+    ));
+    section_header.extend(create_section_header_entry(
+        bss_name,
+        SHT_NOBITS,
+        SHF_WRITE | SHF_ALLOC,
+        0x00,
+        rodata_offset + rodata.len() as u32,
+        bss_size,
+        0x00,
+        0x00,
+        4,
+        0x00,
+    ));
+    section_header.extend(create_section_header_entry(
+        symtab_name,
+        SHT_SYMTAB,
+        0x00,
+        0x00,
+        symtab_offset,
+        symtab.len() as u32,
+        STRTAB_INDEX,
+        first_global_symbol,
+        4,
+        SYMBOL_TABLE_ENTRY_SIZE,
+    ));
+    section_header.extend(create_section_header_entry(
+        strtab_name,
+        SHT_STRTAB,
+        0x00,
+        0x00,
+        strtab_offset,
+        strtab.bytes.len() as u32,
+        0x00,
+        0x00,
+        1,
+        0x00,
+    ));
+    section_header.extend(create_section_header_entry(
+        rel_text_name,
+        SHT_REL,
+        0x00,
+        0x00,
+        rel_text_offset,
+        rel_text.len() as u32,
+        SYMTAB_INDEX,
+        u32::from(TEXT_INDEX),
+        4,
+        RELOCATION_ENTRY_SIZE,
+    ));
+    section_header.extend(create_section_header_entry(
+        rel_data_name,
+        SHT_REL,
+        0x00,
+        0x00,
+        rel_data_offset,
+        rel_data.len() as u32,
+        SYMTAB_INDEX,
+        u32::from(DATA_INDEX),
+        4,
+        RELOCATION_ENTRY_SIZE,
+    ));
+    section_header.extend(create_section_header_entry(
+        rel_rodata_name,
+        SHT_REL,
+        0x00,
+        0x00,
+        rel_rodata_offset,
+        rel_rodata.len() as u32,
+        SYMTAB_INDEX,
+        u32::from(RODATA_INDEX),
+        4,
+        RELOCATION_ENTRY_SIZE,
+    ));
+    section_header.extend(create_section_header_entry(
+        shstrtab_name,
+        SHT_STRTAB,
+        0x00,
+        0x00,
+        shstrtab_offset,
+        shstrtab.bytes.len() as u32,
+        0x00,
+        0x00,
+        1,
+        0x00,
+    ));
+    let section_header_offset = place(&mut elf, &section_header, 4);
+
+    let elf_header = create_elf_header(ET_REL, 0, 0, section_header_offset, NUMBER_OF_SECTIONS);
+    elf[..ELF_HEADER_SIZE as usize].copy_from_slice(&elf_header);
+    elf
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The source contained errors. They have already been reported
+    // on stderr by run.
+    Assembly(Vec<Diagnostic>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "error: {}", e),
+            Error::Assembly(diagnostics) if diagnostics.len() == 1 => {
+                write!(f, "error: aborting due to previous error")
+            }
+            Error::Assembly(diagnostics) => write!(
+                f,
+                "error: aborting due to {} previous errors",
+                diagnostics.len()
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod test_elf {
+    use super::*;
+
+    fn segment(bytes: u32, memory_size: u32) -> Segment {
+        Segment {
+            name: DATA_SECTION_NAME.to_string(),
+            sh_type: SHT_PROGBITS,
+            sh_flags: SHF_WRITE | SHF_ALLOC,
+            p_flags: PF_R | PF_W,
+            bytes: vec![0; bytes as usize],
+            memory_size,
+        }
+    }
+
+    #[test]
+    fn test_section_header_length() {
+        const BYTES_PER_FIELD: usize = 4;
+        const FIELDS_PER_ENTRY: usize = 10;
+        const ENTRIES: usize = 3;
+
+        let segments = [segment(0, 0)];
+        assert_eq!(
+            create_section_header(&create_layout(&segments, 0), &segments, None, 0).len(),
+            BYTES_PER_FIELD * FIELDS_PER_ENTRY * ENTRIES
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let segments = [
+            segment(0, 0),
+            segment(4, 4),
+            segment(4096, 4096),
+            segment(4097, 4097),
+            segment(0x1001, 0x1001),
+            segment(0, 4),
+        ];
+        let layout = create_layout(&segments, 0x20);
+        assert_eq!(layout.string_table_offset, STRTABLE_PHYSICAL_ENTRY_POINT);
+        assert_eq!(
+            layout.segment_offsets,
+            vec![0x1000, 0x2000, 0x3000, 0x4000, 0x6000, 0x8000]
+        );
+        assert_eq!(Layout::address(0x1000), DATA_SECTION_VIRTUAL_START);
+    }
+
+    #[test]
+    fn test_layout_many_sections() {
+        // the program headers don't fit before the string table
+        let segments: Vec<Segment> = (0..41).map(|_| segment(4, 4)).collect();
+        let layout = create_layout(&segments, 0xc00);
+        assert_eq!(layout.string_table_offset, 52 + 41 * 32);
+        assert_eq!(layout.segment_offsets[0], 0x2000);
+    }
+
+    #[test]
+    fn test_program_header_length() {
+        let segments = [segment(0, 0)];
+        assert_eq!(
+            create_program_header(&create_layout(&segments, 0), &segments).len(),
+            8 * 4
+        );
+    }
+
+    #[test]
+    fn test_program_header_bss() {
+        let segments = [segment(5, 5), segment(0, 64)];
+        let layout = create_layout(&segments, 0);
+        let program_header = create_program_header(&layout, &segments);
+        assert_eq!(program_header.len(), 2 * 8 * 4);
+
+        let bss = &program_header[8 * 4..];
+        // p_offset and p_vaddr
+        assert_eq!(&bss[4..8], &0x2000_u32.to_le_bytes());
+        assert_eq!(&bss[8..12], &Layout::address(0x2000).to_le_bytes());
+        // p_filesz and p_memsz
+        assert_eq!(&bss[16..20], &0_u32.to_le_bytes());
+        assert_eq!(&bss[20..24], &64_u32.to_le_bytes());
+    }
+}
+
+pub fn run(config: Config) -> Result<(), Error> {
+    let mut sources = vec![];
+    for filename in &config.filenames {
+        if !config.quiet {
+            println!("compile {}", filename);
+        }
+
+        sources.push(read_source(filename)?);
+    }
+
+    let program = match process(&sources, &config) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                let file = diagnostic.span.as_ref().map(|span| &span.file);
+                let content = sources
+                    .iter()
+                    .find(|source| Some(&source.filename) == file)
+                    .map_or("", |source| source.content.as_str());
+                eprintln!("{}", diagnostic.render(content));
+            }
+            return Err(Error::Assembly(diagnostics));
+        }
+    };
+
+    let (elf, mode) = match config.emit {
+        Emit::Executable => (create_executable(&program, config.strip), 0o755),
+        Emit::Object => (create_object(&program), 0o644),
+    };
+
+    let mut file = fs::File::create(&config.output)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", config.output, e)))?;
+    file.set_permissions(PermissionsExt::from_mode(mode))?;
+    file.write_all(&elf)?;
+
+    if config.verbose {
+        for section in program.data_sections.iter() {
+            println!(
+                "data section {}: {} bytes",
+                section.name,
+                section.bytes.len()
+            );
+        }
+        for reservation in program.reservations.iter() {
+            println!(
+                "reservation {}: {} bytes",
+                reservation.name, reservation.size
+            );
+        }
+        println!("code: {} bytes", program.code.len());
+        if config.emit == Emit::Object {
+            println!("relocations: {}", program.relocations.len());
+        }
+        println!("wrote {}", config.output);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_process {
+    use super::*;
+
+    fn sources(content: &str) -> Vec<Source> {
+        vec![Source {
+            filename: "test.jas".to_string(),
+            content: content.to_string(),
+        }]
+    }
+
+    fn config(error_limit: usize) -> Config {
+        Config {
+            error_limit,
+            ..Default::default()
+        }
+    }
+
+    fn errors(content: &str, error_limit: usize) -> Vec<Diagnostic> {
+        match process(&sources(content), &config(error_limit)) {
+            Ok(_) => panic!("expected errors"),
+            Err(diagnostics) => diagnostics,
+        }
+    }
+
+    #[test]
+    fn test_ok() {
+        let program = process(&sources("⚪ ⬅ $1\n❗ $128"), &config(0)).unwrap();
+        assert!(program.data_sections.is_empty());
+        assert_eq!(program.code.len(), 7);
+    }
+
+    #[test]
+    fn test_reports_all_errors() {
+        let diagnostics = errors("⚪ ⬅ FOO\n🦘 ✉nowhere\n📗data 1, $2\n⚪ ➕", 0);
+        let lines: Vec<_> = diagnostics
+            .iter()
+            .map(|d| d.span.as_ref().unwrap().line)
+            .collect();
+
+        assert_eq!(lines, vec![1, 3, 4, 2]);
+        assert_eq!(diagnostics[0].msg, "Unknown constant FOO");
+        assert_eq!(diagnostics[3].msg, "Unknown label nowhere");
+    }
+
+    #[test]
+    fn test_unknown_label_span() {
+        let diagnostics = errors("⚪ ⬅ $1\n🦘 ✉nowhere", 0);
+        let span = diagnostics[0].span.as_ref().unwrap();
+        assert_eq!((span.line, span.start, span.end), (2, 3, 11));
+    }
+
+    #[test]
+    fn test_missing_operand() {
+        let diagnostics = errors("⚪ ➕", 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().start, 4);
+    }
+
+    #[test]
+    fn test_missing_constant_value() {
+        let diagnostics = errors("🖊FOO", 0);
+        assert_eq!(diagnostics[0].msg, "Constant FOO is missing a value");
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let diagnostics = errors("📪exit:\n↩\n📪exit:", 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 3);
+        assert_eq!(diagnostics[0].notes[0].span.as_ref().unwrap().line, 1);
+    }
+
+    #[test]
+    fn test_label_section_name_clash() {
+        let diagnostics = errors("📗table 1\n📪table:\n📪buffer:\n📦buffer 4\n↩", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Label table has the same name as a data section",
+                "Reservation buffer has the same name as a label",
+            ]
+        );
+        assert_eq!(diagnostics[0].notes[0].span.as_ref().unwrap().line, 1);
+        assert_eq!(diagnostics[1].notes[0].span.as_ref().unwrap().line, 3);
+
+        let diagnostics = errors("📪table:\n📕table 1\n↩", 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].msg,
+            "Data section table has the same name as a label"
+        );
+    }
+
+    #[test]
+    fn test_multiple_sources() {
+        let sources = vec![
+            Source {
+                filename: "a.jas".to_string(),
+                content: "🖊EXIT $1\n🦘 ✉exit".to_string(),
+            },
+            Source {
+                filename: "b.jas".to_string(),
+                content: "📪exit:\n⚪ ⬅ EXIT\n⚪ ⬅ FOO".to_string(),
+            },
+        ];
+
+        let diagnostics = match process(&sources, &config(0)) {
+            Ok(_) => panic!("expected errors"),
+            Err(diagnostics) => diagnostics,
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().file, "b.jas");
+        assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_relocations() {
+        let content = "📗numbers 1, 2\n🔵 ⬅ numbers\n📞 ✉elsewhere\n📪here:\n🦘 ✉here";
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(&sources(content), &config).unwrap();
+
+        assert_eq!(program.labels, vec![("here".to_string(), 10)]);
+        assert_eq!(
+            program.relocations,
+            vec![
+                Relocation {
+                    offset: 1,
+                    symbol: "numbers".to_string(),
+                    addend: 0,
+                    relative: false,
+                },
+                Relocation {
+                    offset: 6,
+                    symbol: "elsewhere".to_string(),
+                    addend: -4,
+                    relative: true,
+                },
+            ]
+        );
+        // the jump to here is resolved immediately
+        assert_eq!(&program.code[10..], &[0xeb, 0xfe]);
+    }
+
+    fn increments(n: usize) -> String {
+        "🔼 ⚪\n".repeat(n)
+    }
+
+    #[test]
+    fn test_short_branches() {
+        let program = process(&sources("📪top:\n🦘= ✉top\n🦘 ✉top"), &config(0)).unwrap();
+        assert_eq!(program.code, vec![0x74, 0xfe, 0xeb, 0xfc]);
+
+        // furthest labels that can be reached with 8 bits
+        let content = format!("🦘 ✉end\n{}📪end:", increments(127));
+        let program = process(&sources(&content), &config(0)).unwrap();
+        assert_eq!(&program.code[..2], &[0xeb, 0x7f]);
+
+        let content = format!("📪start:\n{}🦘≠ ✉start", increments(126));
+        let program = process(&sources(&content), &config(0)).unwrap();
+        assert_eq!(&program.code[126..], &[0x75, 0x80]);
+    }
+
+    #[test]
+    fn test_near_branches() {
+        let content = format!("🦘 ✉end\n{}📪end:", increments(128));
+        let program = process(&sources(&content), &config(0)).unwrap();
+        assert_eq!(&program.code[..5], &[0xe9, 0x80, 0x00, 0x00, 0x00]);
+
+        let content = format!("📪start:\n{}🦘≠ ✉start", increments(127));
+        let program = process(&sources(&content), &config(0)).unwrap();
+        assert_eq!(&program.code[127..], &[0x0f, 0x85, 0x7b, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_branch_relaxation() {
+        // The second jump doesn't fit in 8 bits. Making it near moves
+        // a out of reach of the first jump.
+        let content = format!(
+            "🦘 ✉a\n🦘 ✉b\n{}📪a:\n{}📪b:\n⚪ ⬅ $1",
+            increments(124),
+            increments(4)
+        );
+        let program = process(&sources(&content), &config(0)).unwrap();
+
+        assert_eq!(&program.code[..5], &[0xe9, 0x81, 0x00, 0x00, 0x00]);
+        assert_eq!(&program.code[5..10], &[0xe9, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            program.labels,
+            vec![("a".to_string(), 134), ("b".to_string(), 138)]
+        );
+        let lines: Vec<usize> = program.lines.iter().map(|line| line.0).collect();
+        assert_eq!(&lines[..3], &[0, 5, 10]);
+        assert_eq!(lines.last(), Some(&138));
+    }
+
+    #[test]
+    fn test_jump_conditions() {
+        let content = "📪top:\n🦘o ✉top\n🦘¬s ✉top\n🦘<u ✉top\n🦘≥u ✉top\n🦘p ✉top\n🦘🔵0 ✉top";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![0x70, 0xfe, 0x79, 0xfc, 0x72, 0xfa, 0x73, 0xf8, 0x7a, 0xf6, 0xe3, 0xf4]
+        );
+    }
+
+    #[test]
+    fn test_short_only_branch_errors() {
+        let content = format!("🦘🔵0 ✉end\n{}📪end:\n🦘🔵0 ✉nowhere", increments(128));
+        let diagnostics = errors(&content, 0);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].msg,
+            "Label end is too far away, this jump can only reach 128 bytes back or 127 bytes forward"
+        );
+        assert_eq!(diagnostics[1].msg, "Unknown label nowhere");
+
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let diagnostics = process(&sources("🦘🔵0 ✉elsewhere"), &config)
+            .err()
+            .unwrap();
+        assert_eq!(
+            diagnostics[0].msg,
+            "Label elsewhere should be defined in this file, this jump can only reach 128 bytes back or 127 bytes forward"
+        );
+    }
+
+    #[test]
+    fn test_external_branch() {
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(&sources("🦘= ✉elsewhere"), &config).unwrap();
+
+        assert_eq!(program.code, vec![0x0f, 0x84, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            program.relocations,
+            vec![Relocation {
+                offset: 2,
+                symbol: "elsewhere".to_string(),
+                addend: -4,
+                relative: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_executable_relocation() {
+        let program = process(&sources("📗a 1\n📗b 2\n⚪ ⬅ b"), &config(0)).unwrap();
+        let elf = create_executable(&program, true);
+        let code_start = DATA_SECTION_PHYSICAL_START as usize + 2 * PAGE_SIZE as usize;
+        let address = DATA_SECTION_VIRTUAL_START + PAGE_SIZE;
+
+        assert_eq!(elf[code_start], 0xb8);
+        assert_eq!(&elf[code_start + 1..code_start + 5], &address.to_le_bytes());
+    }
+
+    #[test]
+    fn test_large_data_section() {
+        let table: Vec<String> = (0..3000).map(|i| i.to_string()).collect();
+        let content = format!("📗table {}\n📗end 7\n⚪ ⬅ end", table.join(" "));
+        let program = process(&sources(&content), &config(0)).unwrap();
+        let elf = create_executable(&program, true);
+
+        // 12000 bytes of table take up 3 pages
+        let end_offset = 0x1000 + 3 * 0x1000;
+        let code_offset = end_offset + 0x1000;
+        assert_eq!(
+            &elf[0x1000 + 4 * 2999..0x1000 + 4 * 3000],
+            &2999_i32.to_le_bytes()
+        );
+        assert_eq!(&elf[end_offset..end_offset + 4], &7_i32.to_le_bytes());
+        assert_eq!(
+            &elf[code_offset + 1..code_offset + 5],
+            &Layout::address(end_offset as u32).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_label_addresses() {
+        let content = "📗table ✉first, ✉second\n📪first:\n⚪ ⬅ ✉second\n📥 ✉first\n📪second:\n↩";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(program.code, vec![0xb8, 0, 0, 0, 0, 0x68, 0, 0, 0, 0, 0xc3]);
+        assert_eq!(
+            program.relocations,
+            vec![
+                Relocation {
+                    offset: 1,
+                    symbol: "second".to_string(),
+                    addend: 0,
+                    relative: false,
+                },
+                Relocation {
+                    offset: 6,
+                    symbol: "first".to_string(),
+                    addend: 0,
+                    relative: false,
+                },
+            ]
+        );
+        assert_eq!(program.data_sections[0].bytes, vec![0; 8]);
+        assert_eq!(program.data_sections[0].relocations[1].offset, 4);
+        assert_eq!(program.data_sections[0].relocations[1].symbol, "second");
+    }
+
+    #[test]
+    fn test_label_address_errors() {
+        let diagnostics = errors("⚪ ⬅ ✉nowhere\n📗t ✉nothere\n📗u 📏16 ✉t", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Label addresses are 32 bit, ✉t can't be used after 📏16",
+                "Unknown label nowhere",
+                "Unknown label nothere",
+            ]
+        );
+        assert_eq!(diagnostics[1].span.as_ref().unwrap().start, 5);
+    }
+
+    #[test]
+    fn test_store() {
+        let content = "📗n 0\nn ⬅ 🔵\n$4~⬇ ⬅ $1\n⚪ ⬅ $4~⬇";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![0x89, 0x0d, 0, 0, 0, 0, 0xc7, 0x45, 0x04, 1, 0, 0, 0, 0x8b, 0x45, 0x04]
+        );
+        assert_eq!(program.relocations[0].offset, 2);
+        assert!(!program.relocations[0].relative);
+    }
+
+    #[test]
+    fn test_sized_operands() {
+        let content = "📗n 0
+⚪8 ⬅ $65
+🔵16 ⬅ $258
+⚪8 ⬅ 🔴8
+⚫16 ⬅ $2~⬇~📏16
+⚪8 ⬅ $0~🔵
+$1~⬇~📏8 ⬅ $7
+$0~🟢 ⬅ ⚪16
+n 📏8 ⬅ 🔵8
+n 📏16 ⬅ $5";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0xb0, 0x41, 0x66, 0xb9, 0x02, 0x01, 0x88, 0xd8, 0x66, 0x8b, 0x55, 0x02, 0x8a, 0x01,
+                0xc6, 0x45, 0x01, 0x07, 0x66, 0x89, 0x06, 0x88, 0x0d, 0, 0, 0, 0, 0x66, 0xc7, 0x05,
+                0, 0, 0, 0, 0x05, 0x00
+            ]
+        );
+        assert_eq!(program.relocations[1].offset, 30);
+    }
+
+    #[test]
+    fn test_sized_arithmetic() {
+        let content = "⚪8 ➕ $1
+🔵16 ➖ $1000
+⚫8 ➕ 🔴8
+⚪16 ∧ ⚫16
+🔴8 ∨ $0x80
+🔵8 ⊻ 🔵8
+⚖ ⚪8, $'a'
+⚖ ⚪16, $-1
+⚖ ⚫8, 🔴8
+⚪8 ≪ $1
+🔵16 ⋙ $3
+⚫8 ↺ 🔵
+🔼 ⚪8
+🔽 🔵16
+¬ ⚪8
+± 🔵16";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0x80, 0xc0, 0x01, 0x66, 0x81, 0xe9, 0xe8, 0x03, 0x00, 0xda, 0x66, 0x21, 0xd0, 0x80,
+                0xcb, 0x80, 0x30, 0xc9, 0x80, 0xf8, 0x61, 0x66, 0x83, 0xf8, 0xff, 0x38, 0xda, 0xd0,
+                0xe0, 0x66, 0xc1, 0xf9, 0x03, 0xd2, 0xc2, 0xfe, 0xc0, 0x66, 0x49, 0xf6, 0xd0, 0x66,
+                0xf7, 0xd9
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sib_addressing() {
+        let content = "⚪ ⬅ $8~⬇~🔵~$4
+⚪ ⬅ $0~🔵~⚫
+$0~⬇~🟢~$2~📏16 ⬅ $1
+📥 $1000~🔵
+⚪ ⬅0 $300~◀~🔴~$8~📏8";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0x8b, 0x44, 0x8d, 0x08, 0x8b, 0x04, 0x11, 0x66, 0xc7, 0x44, 0x75, 0x00, 0x01, 0x00,
+                0xff, 0xb1, 0xe8, 0x03, 0x00, 0x00, 0x0f, 0xb6, 0x84, 0xdc, 0x2c, 0x01, 0x00, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_immediates() {
+        let content = "⚖ ⚪, $1000\n📥 $4096~⬇\n⚪ ⬅ $200~🔵~🔴";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0x81, 0xf8, 0xe8, 0x03, 0x00, 0x00, 0xff, 0xb5, 0x00, 0x10, 0x00, 0x00, 0x8b, 0x84,
+                0x19, 0xc8, 0x00, 0x00, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indirect_jumps() {
+        let content = "🦘 ⚪\n📞 $4~🔴\n🦘 $0~🔵~🟢~$4\n📞 $-8~⬇";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![0xff, 0xe0, 0xff, 0x53, 0x04, 0xff, 0x24, 0xb1, 0xff, 0x55, 0xf8]
+        );
+
+        let diagnostics = errors("🦘 ⚪16\n📞 ✉x 🔴", 0);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].msg,
+            "Only 32 bit registers can be used here, ⚪16 is 16 bit"
+        );
+    }
+
+    #[test]
+    fn test_table_jumps() {
+        let content = "📕table ✉a ✉b\n🦘 table~🔵~$4\n⚪ ⬅ ✉a~🔴\n📪a:\n↩\n📪b:\n↩";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0xff, 0x24, 0x8d, 0x00, 0x00, 0x00, 0x00, 0x8b, 0x83, 0x00, 0x00, 0x00, 0x00, 0xc3,
+                0xc3
+            ]
+        );
+        let relocations: Vec<(usize, &str, bool)> = program
+            .relocations
+            .iter()
+            .map(|r| (r.offset, r.symbol.as_str(), r.relative))
+            .collect();
+        assert_eq!(relocations, vec![(3, "table", false), (9, "a", false)]);
+    }
+
+    #[test]
+    fn test_sib_errors() {
+        let diagnostics = errors("⚪ ⬅ $0~🔵~◀\n⚪ ⬅ $0~🔵~⚫~$3\n⚪ ⬅0 $0~🔵", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "◀ can't be used as index",
+                "Scale 3 should be 1, 2, 4 or 8",
+                "⬅0 needs the size of the memory operand, e.g. 0~🔵~📏8",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_move_extend() {
+        let content = "⚪ ⬅0 $0~🔵~📏8\n🔴 ⬅± ⚫16\n⚪16 ⬅0 🔵8";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![0x0f, 0xb6, 0x01, 0x0f, 0xbf, 0xda, 0x66, 0x0f, 0xb6, 0xc1]
+        );
+    }
+
+    #[test]
+    fn test_conditional_set_move() {
+        let content = "✔< ⚪8\n✔≥u 🔴8\n⚪ ⬅= 🔵\n🟢 ⬅>u $4~⬇\n🔴 ⬅¬o $0~🔵~🟢~$4~📏32";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0x0f, 0x9c, 0xc0, 0x0f, 0x93, 0xc3, 0x0f, 0x44, 0xc1, 0x0f, 0x47, 0x75, 0x04, 0x0f,
+                0x41, 0x1c, 0xb1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditional_set_move_errors() {
+        let content = "✔< ⚪\n⚪8 ⬅< 🔵\n🔴 ⬅≠ $0~🔵~📏8\n✔< $0~🔵";
+        let diagnostics = errors(content, 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Size mismatch: ⚪ is 32 bit but should be 8 bit",
+                "Only 32 bit registers can be used here, ⚪8 is 8 bit",
+                "⬅≠ can only move 32 bit values, the memory operand is 8 bit",
+                "Grammatical error:  ✔< 0, 0 should be a {Register}.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_size_errors() {
+        let content = "⚪8 ⬅ 🔴
+⚪ ⬅ $0~🔵~📏8
+⚪8 ⬅ $256
+⚪8 ✖ 🔴8
+⚪16 ➕ 🔴
+⚪8 ⬅0 🔵16
+📗n 0
+n 📏8 ⬅ 🔵
+📤 ⚪8
+📤 ⚪16";
+        let diagnostics = errors(content, 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Size mismatch: 🔴 is 32 bit but should be 8 bit",
+                "Size mismatch: ⚪ is 32 bit but should be 8 bit",
+                "Value 256 doesn't fit in 8 bits",
+                "Only 32 bit registers can be used here, ⚪8 is 8 bit",
+                "Size mismatch: 🔴 is 32 bit but should be 16 bit",
+                "⬅0 can only move into a larger register, ⚪8 is 8 bit and 🔵16 is 16 bit",
+                "Size mismatch: 🔵 is 32 bit but should be 8 bit",
+                "Only 32 bit registers can be used here, ⚪8 is 8 bit",
+                "Only 32 bit registers can be used here, ⚪16 is 16 bit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_section_values() {
+        let content = "📗t 📏8 1, 255 📏16 258 📏32 3 \"a b\\n\" c\"c\"";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.data_sections[0].bytes,
+            vec![1, 255, 2, 1, 3, 0, 0, 0, b'a', b' ', b'b', b'\n', b'c', 0]
+        );
+    }
+
+    #[test]
+    fn test_data_value_too_large() {
+        let diagnostics = errors("📗t 📏8 256\n📗u 📏16 65536", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Data value 256 doesn't fit in 8 bits",
+                "Data value 65536 doesn't fit in 16 bits",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_section_signed_values() {
+        let content = "📗t 📏8 -1, 0x7f, 'A' 📏16 -32768 📏32 0b101";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.data_sections[0].bytes,
+            vec![0xff, 0x7f, 0x41, 0x00, 0x80, 0x05, 0, 0, 0]
+        );
+
+        let diagnostics = errors("📗t 📏8 -129\n📗u 📏16 -32769", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Data value -129 doesn't fit in 8 bits",
+                "Data value -32769 doesn't fit in 16 bits",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signed_immediates() {
+        let content = "⚪ ⬅ $-1\n⚪ ➕ $0xff\n⚪8 ⬅ $'A'\n⚖ ⚪, $-1\n⚪ ⬅ $-8~⬇";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            program.code,
+            vec![
+                0xb8, 0xff, 0xff, 0xff, 0xff, 0x81, 0xc0, 0xff, 0x00, 0x00, 0x00, 0xb0, 0x41, 0x83,
+                0xf8, 0xff, 0x8b, 0x45, 0xf8
+            ]
+        );
+
+        let diagnostics = errors("⚪8 ⬅ $-129\n⚪ ≪ $-1\n❗ $-128", 0);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Value -129 doesn't fit in 8 bits",
+                "Shift count -1 can't be negative",
+                "Interrupt -128 should be from 0 to 255",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reservation() {
+        let content = "📦flag 1\n📦buffer 65536\n⚪ ⬅ buffer\n📥 flag";
+        let program = process(&sources(content), &config(0)).unwrap();
+        assert_eq!(
+            reservation_offsets(&program.reservations),
+            (vec![0, 4], 65540)
+        );
+
+        let elf = create_executable(&program, true);
+        let code_offset = 0x1000;
+        let bss_address = Layout::address(0x2000);
+        assert_eq!(
+            &elf[code_offset + 1..code_offset + 5],
+            &(bss_address + 4).to_le_bytes()
+        );
+        assert_eq!(
+            &elf[code_offset + 6..code_offset + 10],
+            &bss_address.to_le_bytes()
+        );
+        // nothing is written for .bss
+        assert_eq!(elf.len(), code_offset + 12 + 4 * 40);
+    }
+
+    #[test]
+    fn test_reservation_errors() {
+        let content = "📦a\n📦b $4\n📦c 4 4\n📗d 1\n📦d 4\n📦e 4\n📦e 4\n📕e 1\n📦f 0\n⚪ ⬅ f";
+        let messages: Vec<String> = errors(content, 0).into_iter().map(|d| d.msg).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Reservation a is missing a size",
+                "Unsupported token in reservation: 4",
+                "Unsupported token in reservation: 4",
+                "Reservation d has the same name as a data section",
+                "Reservation e is defined more than once",
+                "Data section e has the same name as a reservation",
+                "Invalid reservation size 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let content = "📗numbers 1, 2\n📞 ✉double\n📪double:\n📪loop:\n🦘 ✉loop";
+        let program = process(&sources(content), &config(0)).unwrap();
+        let (segments, symbols, _) = create_segments(&program);
+        let layout = create_layout(&segments, 0);
+        let code_address = DATA_SECTION_VIRTUAL_START + PAGE_SIZE;
+
+        let (symbols, names) = create_symbol_table(&symbols, &layout);
+        let symbols: Vec<&[u8]> = symbols.chunks(SYMBOL_TABLE_ENTRY_SIZE as usize).collect();
+
+        assert_eq!(names.bytes, b"\0numbers\0double\0loop\0".to_vec());
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(
+            symbols[1],
+            create_symbol_table_entry(1, DATA_SECTION_VIRTUAL_START, 8, STB_GLOBAL, STT_OBJECT, 1)
+                .as_slice()
+        );
+        assert_eq!(
+            symbols[2],
+            create_symbol_table_entry(9, code_address + 5, 0, STB_GLOBAL, STT_FUNC, 2).as_slice()
+        );
+        assert_eq!(
+            symbols[3],
+            create_symbol_table_entry(16, code_address + 5, 0, STB_GLOBAL, STT_NOTYPE, 2)
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_read_only_sections() {
+        let content = "📗a 1\n📕b 📏8 2\n📕c 3\n⚪ ⬅ c";
+        let program = process(&sources(content), &config(0)).unwrap();
+        let (segments, symbols, code_segment) = create_segments(&program);
+
+        let names: Vec<&str> = segments.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["a", RODATA_SECTION_NAME, CODE_SECTION_NAME]);
+        assert_eq!(code_segment, 2);
+        assert_eq!(segments[1].sh_flags, SHF_ALLOC);
+        assert_eq!(segments[1].p_flags, PF_R);
+        // c is aligned to 4 bytes
+        assert_eq!(segments[1].bytes, vec![2, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!((symbols[2].segment, symbols[2].offset), (1, 4));
+
+        let elf = create_executable(&program, true);
+        let code_offset = 0x3000;
+        let c_address = Layout::address(0x2000 + 4);
+        assert_eq!(
+            &elf[code_offset + 1..code_offset + 5],
+            &c_address.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let program = process(&sources("# comment\n📪start:\n⚪ ⬅ $1\n\n↩"), &config(0)).unwrap();
+        let lines: Vec<(usize, usize)> = program
+            .lines
+            .iter()
+            .map(|(offset, span)| (*offset, span.line))
+            .collect();
+
+        assert_eq!(lines, vec![(0, 3), (5, 5)]);
+    }
+
+    #[test]
+    fn test_strip() {
+        let program = process(&sources("📪exit:\n⚪ ⬅ $1"), &config(0)).unwrap();
+        let stripped = create_executable(&program, true);
+        let unstripped = create_executable(&program, false);
+        let symtab_offset = DATA_SECTION_PHYSICAL_START as usize + 8;
+
+        // code and the section header table with 3 sections
+        assert_eq!(
+            stripped.len(),
+            DATA_SECTION_PHYSICAL_START as usize + 8 + 3 * 40
+        );
+        assert_eq!(
+            &unstripped[symtab_offset + 16..symtab_offset + 32],
+            create_symbol_table_entry(1, DATA_SECTION_VIRTUAL_START, 0, STB_GLOBAL, STT_NOTYPE, 1)
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_object() {
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(&sources("📪start:\n📞 ✉elsewhere"), &config).unwrap();
+        let elf = create_object(&program);
+
+        // e_type
+        assert_eq!(&elf[16..18], &[0x01, 0x00]);
+        // the addend is stored in place
+        assert_eq!(&elf[64..69], &[0xe8, 0xfc, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_object_symbol_bindings() {
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(
+            &sources("📢exported\n📪_start:\n📪loop:\n📞 ✉elsewhere\n📪exported:\n↩"),
+            &config,
+        )
+        .unwrap();
+        let elf = create_object(&program);
+
+        let shoff = u32::from_le_bytes([elf[32], elf[33], elf[34], elf[35]]) as usize;
+        let symtab_header = &elf[shoff + 5 * 40..shoff + 6 * 40];
+        let symtab_offset = u32::from_le_bytes([
+            symtab_header[16],
+            symtab_header[17],
+            symtab_header[18],
+            symtab_header[19],
+        ]) as usize;
+
+        // null, 4 sections, loop, _start, exported, elsewhere
+        let bindings: Vec<u8> = (0..9)
+            .map(|i| elf[symtab_offset + i * 16 + 12] >> 4)
+            .collect();
+        assert_eq!(bindings, vec![0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        // sh_info is the index of the first global symbol
+        assert_eq!(&symtab_header[28..32], &[6, 0, 0, 0]);
+
+        let diagnostics = errors("📢nowhere\n↩", 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].msg, "Unknown label nowhere");
+    }
+
+    #[test]
+    fn test_object_data_alignment() {
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(&sources("📗a 📏8 1, 2, 3\n📗b 5\n⚪ ⬅ b"), &config).unwrap();
+        let elf = create_object(&program);
+
+        // the addend is the offset of b in .data
+        assert_eq!(&elf[64..69], &[0xb8, 0x04, 0x00, 0x00, 0x00]);
+
+        let shoff = u32::from_le_bytes([elf[32], elf[33], elf[34], elf[35]]) as usize;
+        let data_header = &elf[shoff + 2 * 40..shoff + 3 * 40];
+        let data_offset = u32::from_le_bytes([
+            data_header[16],
+            data_header[17],
+            data_header[18],
+            data_header[19],
+        ]) as usize;
+        // sh_size and sh_addralign
+        assert_eq!(&data_header[20..24], &[8, 0, 0, 0]);
+        assert_eq!(&data_header[32..36], &[4, 0, 0, 0]);
+        assert_eq!(
+            &elf[data_offset..data_offset + 8],
+            &[1, 2, 3, 0, 5, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_object_data_relocations() {
+        let config = Config {
+            emit: Emit::Object,
+            ..Default::default()
+        };
+        let program = process(&sources("📕table 0, ✉elsewhere\n↩"), &config).unwrap();
+        let elf = create_object(&program);
+
+        // e_shnum and e_shstrndx
+        assert_eq!(&elf[48..52], &[0x0b, 0x00, 0x0a, 0x00]);
+        assert_eq!(program.data_sections[0].relocations[0].offset, 4);
+    }
+
+    #[test]
+    fn test_error_limit() {
+        let diagnostics = errors("⚪ ⬅ A\n⚪ ⬅ B\n⚪ ⬅ C\n⚪ ⬅ D", 2);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[1].msg, "Unknown constant B");
+        assert_eq!(diagnostics[1].notes.len(), 1);
+
+        // nothing was left out
+        let diagnostics = errors("⚪ ⬅ A\n⚪ ⬅ B", 2);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[1].notes.is_empty());
+    }
 }
@@ -14,9 +14,28 @@
 extern crate jvo_asm;
 
 use jvo_asm::config::Config;
-use std::env;
+use std::{env, process};
 
 fn main() {
-    let config = Config::new(env::args().collect()).unwrap();
-    jvo_asm::run(config).unwrap();
+    let args: Vec<String> = env::args().collect();
+    let program_name = args[0].clone();
+    let config = Config::new(args).unwrap_or_else(|usage| {
+        eprintln!("{}", usage);
+        process::exit(2);
+    });
+
+    if config.help {
+        println!("{}", Config::usage(&program_name));
+        return;
+    }
+
+    if config.version {
+        println!("jvo-asm {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    if let Err(e) = jvo_asm::run(config) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
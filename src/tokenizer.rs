@@ -12,29 +12,159 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use common::*;
-use std::{error, fmt};
+use diagnostic::Diagnostic;
+use std::error;
+
+// Returns the bytes of a string literal. Literals are quoted and
+// can contain the escapes \n, \t, \r, \0, \\, \" and \x followed
+// by two hex digits. A literal prefixed with c (c"hi") is NUL
+// terminated.
+pub fn string_bytes(literal: &str) -> Result<Vec<u8>, String> {
+    let (quoted, terminated) = match literal.strip_prefix('c') {
+        Some(quoted) => (quoted, true),
+        None => (literal, false),
+    };
+    let contents = match quoted
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+    {
+        Some(contents) => contents,
+        None => return Err(format!("Invalid string: {}", literal)),
+    };
+
+    let mut bytes = vec![];
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('0') => bytes.push(0x00),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if digits.len() == 2 => bytes.push(byte),
+                    _ => return Err(format!("Invalid escape \\x{} in string", digits)),
+                }
+            }
+            Some(c) => return Err(format!("Invalid escape \\{} in string", c)),
+            None => return Err("String ends with an unfinished escape".to_string()),
+        }
+    }
 
-#[derive(Debug, Clone)]
-struct TokenizeError {
-    msg: String,
+    if terminated {
+        bytes.push(0x00);
+    }
+
+    Ok(bytes)
 }
 
-impl fmt::Display for TokenizeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.msg)
+// Returns the value of a number literal. Numbers are decimal,
+// hexadecimal (0xff), binary (0b1010) or a character ('A'), and can be
+// negative. They should fit in 32 bits, signed or unsigned. Errors
+// are a sentence to append to e.g. "Invalid value: $x.".
+pub fn parse_number(literal: &str) -> Result<i64, String> {
+    let invalid = || "Should be a number.".to_string();
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, literal),
+    };
+
+    let value = if let Some(character) = digits
+        .strip_prefix('\'')
+        .and_then(|quoted| quoted.strip_suffix('\''))
+    {
+        let mut chars = character.chars();
+        let c = match (chars.next(), chars.next(), chars.next()) {
+            (Some('\\'), Some(escaped), None) => match escaped {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                _ => return Err(format!("Invalid escape \\{} in character.", escaped)),
+            },
+            (Some(c), None, None) => c,
+            _ => return Err("Should be a single character.".to_string()),
+        };
+        i64::from(u32::from(c))
+    } else {
+        let (radix, digits) = if let Some(digits) = digits.strip_prefix("0x") {
+            (16, digits)
+        } else if let Some(digits) = digits.strip_prefix("0b") {
+            (2, digits)
+        } else {
+            (10, digits)
+        };
+        // from_str_radix also accepts a sign
+        if digits.starts_with('+') || digits.starts_with('-') {
+            return Err(invalid());
+        }
+        match i64::from_str_radix(digits, radix) {
+            Ok(value) => value,
+            Err(_) if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) => {
+                return Err("Doesn't fit in 32 bits.".to_string())
+            }
+            Err(_) => return Err(invalid()),
+        }
+    };
+
+    let value = if negative { -value } else { value };
+    if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+        return Err("Doesn't fit in 32 bits.".to_string());
     }
+
+    Ok(value)
 }
 
-impl error::Error for TokenizeError {
-    fn description(&self) -> &str {
-        self.msg.as_str()
+// Returns the length in bytes of the string or character literal line
+// starts with (including the quotes) or None if it doesn't start with
+// one. Character literals can be prefixed with $.
+fn string_literal_len(line: &str) -> Option<Result<usize, ()>> {
+    let (prefix_len, quote) = if line.starts_with('"') {
+        (1, '"')
+    } else if line.starts_with("c\"") {
+        (2, '"')
+    } else if line.starts_with('\'') {
+        (1, '\'')
+    } else if line.starts_with("$'") {
+        (2, '\'')
+    } else {
+        return None;
+    };
+
+    let mut escaped = false;
+    for (i, c) in line[prefix_len..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return Some(Ok(prefix_len + i + 1)),
+            _ => {}
+        }
     }
+
+    Some(Err(()))
 }
 
-fn tokenize_word(word: &str) -> Result<Token, Box<dyn error::Error>> {
+// True if word is operator followed by a condition, e.g. 🦘<u.
+fn is_conditional(word: &str, operator: char) -> bool {
+    word.starts_with(operator) && condition_code(&word[operator.len_utf8()..]).is_some()
+}
+
+fn tokenize_word(word: &str, span: Span) -> Result<Token, Box<dyn error::Error>> {
     let mut token = Token {
         t: None,
         value: word.to_string(),
+        span,
     };
 
     match word {
@@ -50,32 +180,80 @@ fn tokenize_word(word: &str) -> Result<Token, Box<dyn error::Error>> {
         "✖" => {
             token.t = Some(TokenType::Multiply);
         }
+        "➗" => {
+            token.t = Some(TokenType::Divide);
+        }
+        "➗u" => {
+            token.t = Some(TokenType::DivideUnsigned);
+        }
+        "♻" => {
+            token.t = Some(TokenType::Remainder);
+        }
+        "♻u" => {
+            token.t = Some(TokenType::RemainderUnsigned);
+        }
+        "∧" => {
+            token.t = Some(TokenType::And);
+        }
+        "∨" => {
+            token.t = Some(TokenType::Or);
+        }
+        "⊻" => {
+            token.t = Some(TokenType::Xor);
+        }
+        "¬" => {
+            token.t = Some(TokenType::Not);
+        }
+        "≪" => {
+            token.t = Some(TokenType::ShiftLeft);
+        }
+        "≫" => {
+            token.t = Some(TokenType::ShiftRight);
+        }
+        "⋙" => {
+            token.t = Some(TokenType::ShiftRightArithmetic);
+        }
+        "↺" => {
+            token.t = Some(TokenType::RotateLeft);
+        }
+        "↻" => {
+            token.t = Some(TokenType::RotateRight);
+        }
+        "🔼" => {
+            token.t = Some(TokenType::Increment);
+        }
+        "🔽" => {
+            token.t = Some(TokenType::Decrement);
+        }
+        "±" => {
+            token.t = Some(TokenType::Negate);
+        }
         "⬅" => {
             token.t = Some(TokenType::Move);
         }
+        "⬅0" => {
+            token.t = Some(TokenType::MoveZeroExtend);
+        }
+        "⬅±" => {
+            token.t = Some(TokenType::MoveSignExtend);
+        }
         "❗" => {
             token.t = Some(TokenType::Interrupt);
         }
-        "⚪" | "🔴" | "🔵" | "⚫" | "◀" | "⬇" => {
+        "⚪" | "🔴" | "🔵" | "⚫" | "◀" | "⬇" | "🟢" | "🟣" => {
             token.t = Some(TokenType::Register);
         }
-        "🦘=" => {
-            token.t = Some(TokenType::JumpIfEqual);
-        }
-        "🦘≠" => {
-            token.t = Some(TokenType::JumpIfNotEqual);
-        }
-        "🦘<" => {
-            token.t = Some(TokenType::JumpIfLess);
-        }
-        "🦘≤" => {
-            token.t = Some(TokenType::JumpIfLessEqual);
+        // 16 bit registers
+        "⚪16" | "🔴16" | "🔵16" | "⚫16" | "◀16" | "⬇16" | "🟢16" | "🟣16" => {
+            token.t = Some(TokenType::Register);
         }
-        "🦘>" => {
-            token.t = Some(TokenType::JumpIfGreater);
+        // 8 bit registers, only the low byte of the first four
+        // registers can be addressed
+        "⚪8" | "🔴8" | "🔵8" | "⚫8" => {
+            token.t = Some(TokenType::Register);
         }
-        "🦘≥" => {
-            token.t = Some(TokenType::JumpIfGreaterEqual);
+        "🦘🔵0" => {
+            token.t = Some(TokenType::JumpIfCounterZero);
         }
         "🦘" => {
             token.t = Some(TokenType::Jump);
@@ -92,6 +270,15 @@ fn tokenize_word(word: &str) -> Result<Token, Box<dyn error::Error>> {
         "⚖" => {
             token.t = Some(TokenType::Compare);
         }
+        _ if is_conditional(word, '🦘') => {
+            token.t = Some(TokenType::JumpIf);
+        }
+        _ if is_conditional(word, '⬅') => {
+            token.t = Some(TokenType::MoveIf);
+        }
+        _ if is_conditional(word, '✔') => {
+            token.t = Some(TokenType::SetIf);
+        }
         _ if word.starts_with("🖊") => {
             token.t = Some(TokenType::Constant);
             token.value.remove(0);
@@ -102,25 +289,62 @@ fn tokenize_word(word: &str) -> Result<Token, Box<dyn error::Error>> {
             let to_trim: &[_] = &[':', '📪'];
             token.value = token.value.trim_matches(to_trim).to_string();
         }
+        _ if word.starts_with("📢") => {
+            token.t = Some(TokenType::Export);
+            token.value.remove(0);
+        }
         _ if word.starts_with("📗") => {
             token.t = Some(TokenType::Section);
             token.value.remove(0);
         }
+        _ if word.starts_with("📕") => {
+            token.t = Some(TokenType::ReadOnlySection);
+            token.value.remove(0);
+        }
+        _ if word.starts_with("📦") => {
+            token.t = Some(TokenType::Reservation);
+            token.value.remove(0);
+        }
+        _ if word.starts_with("📏") => {
+            token.t = Some(TokenType::DataWidth);
+            token.value.remove(0);
+            if !["8", "16", "32"].contains(&token.value.as_str()) {
+                return Err(Box::new(Diagnostic::error(
+                    format!("Invalid data width: {}. Should be 8, 16 or 32.", word),
+                    Some(token.span),
+                )));
+            }
+        }
+        _ if word.starts_with('"') || word.starts_with("c\"") => {
+            if let Err(msg) = string_bytes(word) {
+                return Err(Box::new(Diagnostic::error(msg, Some(token.span))));
+            }
+
+            token.t = Some(TokenType::String);
+        }
         _ if word.starts_with("✉") => {
             token.t = Some(TokenType::LabelReference);
             token.value.remove(0);
         }
         _ if word.starts_with('$') => {
-            if word[1..].parse::<u32>().is_err() {
-                return Err(Box::new(TokenizeError {
-                    msg: format!("Invalid value: {}. Should be a number.", word),
-                }));
+            if let Err(msg) = parse_number(&word[1..]) {
+                return Err(Box::new(Diagnostic::error(
+                    format!("Invalid value: {}. {}", word, msg),
+                    Some(token.span),
+                )));
             }
 
             token.t = Some(TokenType::Value);
             token.value.remove(0);
         }
-        _ if word.parse::<u32>().is_ok() => {
+        _ if word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '\'') => {
+            if let Err(msg) = parse_number(word) {
+                return Err(Box::new(Diagnostic::error(
+                    format!("Invalid number: {}. {}", word, msg),
+                    Some(token.span),
+                )));
+            }
+
             token.t = Some(TokenType::Memory);
         }
         _ => {
@@ -131,22 +355,65 @@ fn tokenize_word(word: &str) -> Result<Token, Box<dyn error::Error>> {
     Ok(token)
 }
 
-pub fn tokenize(line: &str) -> Result<Vec<Token>, Box<dyn error::Error>> {
+// Tokenizes a single line. file and line_number are only used to
+// annotate the tokens with their location in the source.
+pub fn tokenize(
+    file: &str,
+    line_number: usize,
+    line: &str,
+) -> Result<Vec<Token>, Box<dyn error::Error>> {
     let mut tokens = vec![];
     let ignore_char = |c: char| c == ',' || c.is_whitespace();
     let is_delimiter = |c: char| c == ' ' || c == '~';
 
-    for word in line.split(is_delimiter) {
-        let word = word.trim_matches(ignore_char);
-        if word.is_empty() {
-            continue;
-        }
-
-        if word.starts_with('#') {
+    // Byte offset of the current word in line.
+    let mut word_start = 0;
+    loop {
+        let rest = &line[word_start..];
+        let trimmed = rest.trim_start_matches(|c| is_delimiter(c) || ignore_char(c));
+        word_start += rest.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
             break;
         }
 
-        let token = tokenize_word(word)?;
+        let start = line[..word_start].chars().count() + 1;
+
+        // Strings can contain delimiters, so a word only ends after
+        // the closing quote.
+        let word_len = match string_literal_len(trimmed) {
+            Some(Ok(len)) => {
+                len + trimmed[len..]
+                    .find(is_delimiter)
+                    .unwrap_or(trimmed.len() - len)
+            }
+            Some(Err(())) => {
+                let kind = if trimmed.starts_with('"') || trimmed.starts_with("c\"") {
+                    "string"
+                } else {
+                    "character"
+                };
+                return Err(Box::new(Diagnostic::error(
+                    format!("Unterminated {}", kind),
+                    Some(Span {
+                        file: file.to_string(),
+                        line: line_number,
+                        start,
+                        end: start + trimmed.chars().count(),
+                    }),
+                )));
+            }
+            None => trimmed.find(is_delimiter).unwrap_or(trimmed.len()),
+        };
+        let word = trimmed[..word_len].trim_end_matches(ignore_char);
+        word_start += word_len;
+
+        let span = Span {
+            file: file.to_string(),
+            line: line_number,
+            start,
+            end: start + word.chars().count(),
+        };
+        let token = tokenize_word(word, span)?;
         tokens.push(token);
     }
 
@@ -159,29 +426,29 @@ mod test_tokenize {
 
     #[test]
     fn test_empty_string() {
-        let tokens = tokenize("").unwrap();
+        let tokens = tokenize("test.jas", 1, "").unwrap();
         assert!(tokens.is_empty());
     }
 
     #[test]
     fn test_const_reference() {
-        let tokens = tokenize("foobar").unwrap();
+        let tokens = tokenize("test.jas", 1, "foobar").unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].t, Some(TokenType::ConstantReference));
     }
 
-    fn verify_ret(tokens: &Vec<Token>) {
+    fn verify_ret(tokens: &[Token]) {
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].t, Some(TokenType::Return));
     }
 
     #[test]
     fn test_ret() {
-        let tokens = tokenize("↩").unwrap();
+        let tokens = tokenize("test.jas", 1, "↩").unwrap();
         verify_ret(&tokens);
     }
 
-    fn verify_add(tokens: &Vec<Token>) {
+    fn verify_add(tokens: &[Token]) {
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0].t, Some(TokenType::Register));
         assert_eq!(tokens[0].value, "⚪");
@@ -194,13 +461,13 @@ mod test_tokenize {
 
     #[test]
     fn test_add() {
-        let tokens = tokenize("⚪ ➕ $5").unwrap();
+        let tokens = tokenize("test.jas", 1, "⚪ ➕ $5").unwrap();
         verify_add(&tokens);
     }
 
     #[test]
     fn test_subtract() {
-        let tokens = tokenize("⚪ ➖ $5").unwrap();
+        let tokens = tokenize("test.jas", 1, "⚪ ➖ $5").unwrap();
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0].t, Some(TokenType::Register));
         assert_eq!(tokens[0].value, "⚪");
@@ -213,7 +480,7 @@ mod test_tokenize {
 
     #[test]
     fn test_multiply() {
-        let tokens = tokenize("⚪ ✖ $5").unwrap();
+        let tokens = tokenize("test.jas", 1, "⚪ ✖ $5").unwrap();
 
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0].t, Some(TokenType::Register));
@@ -225,9 +492,92 @@ mod test_tokenize {
         assert_eq!(tokens[2].value, "5");
     }
 
+    #[test]
+    fn test_divide() {
+        let tokens = tokenize("test.jas", 1, "⚪ ➗ 🔵").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].t, Some(TokenType::Divide));
+
+        let operations: Vec<Option<TokenType>> = ["➗u", "♻", "♻u"]
+            .iter()
+            .map(|word| tokenize("test.jas", 1, word).unwrap()[0].t.clone())
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                Some(TokenType::DivideUnsigned),
+                Some(TokenType::Remainder),
+                Some(TokenType::RemainderUnsigned),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logic() {
+        let tokens = tokenize("test.jas", 1, "⚪ ∧ $5").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].t, Some(TokenType::Register));
+        assert_eq!(tokens[1].t, Some(TokenType::And));
+        assert_eq!(tokens[2].t, Some(TokenType::Value));
+
+        let tokens = tokenize("test.jas", 1, "⚪ ∨ 🔵").unwrap();
+        assert_eq!(tokens[1].t, Some(TokenType::Or));
+
+        let tokens = tokenize("test.jas", 1, "⚪ ⊻ ⚪").unwrap();
+        assert_eq!(tokens[1].t, Some(TokenType::Xor));
+    }
+
+    #[test]
+    fn test_not() {
+        let tokens = tokenize("test.jas", 1, "¬ 🔴").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].t, Some(TokenType::Not));
+        assert_eq!(tokens[1].t, Some(TokenType::Register));
+        assert_eq!(tokens[1].value, "🔴");
+    }
+
+    #[test]
+    fn test_shift() {
+        let tokens = tokenize("test.jas", 1, "⚪ ≪ $3").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].t, Some(TokenType::Register));
+        assert_eq!(tokens[1].t, Some(TokenType::ShiftLeft));
+        assert_eq!(tokens[2].t, Some(TokenType::Value));
+        assert_eq!(tokens[2].value, "3");
+
+        let operations: Vec<Option<TokenType>> = ["≫", "⋙", "↺", "↻"]
+            .iter()
+            .map(|word| tokenize("test.jas", 1, word).unwrap()[0].t.clone())
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                Some(TokenType::ShiftRight),
+                Some(TokenType::ShiftRightArithmetic),
+                Some(TokenType::RotateLeft),
+                Some(TokenType::RotateRight),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_increment_decrement_negate() {
+        let tokens = tokenize("test.jas", 1, "🔼 ⚪").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].t, Some(TokenType::Increment));
+        assert_eq!(tokens[1].t, Some(TokenType::Register));
+        assert_eq!(tokens[1].value, "⚪");
+
+        let tokens = tokenize("test.jas", 1, "🔽 🔵").unwrap();
+        assert_eq!(tokens[0].t, Some(TokenType::Decrement));
+
+        let tokens = tokenize("test.jas", 1, "± 🔴").unwrap();
+        assert_eq!(tokens[0].t, Some(TokenType::Negate));
+    }
+
     #[test]
     fn test_memory() {
-        let tokens = tokenize("321").unwrap();
+        let tokens = tokenize("test.jas", 1, "321").unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].t, Some(TokenType::Memory));
         assert_eq!(tokens[0].value, "321");
@@ -235,15 +585,40 @@ mod test_tokenize {
 
     #[test]
     fn test_label() {
-        let tokens = tokenize("📪my_label:").unwrap();
+        let tokens = tokenize("test.jas", 1, "📪my_label:").unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].t, Some(TokenType::Label));
         assert_eq!(tokens[0].value, "my_label");
     }
 
+    #[test]
+    fn test_export() {
+        let tokens = tokenize("test.jas", 1, "📢my_label").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].t, Some(TokenType::Export));
+        assert_eq!(tokens[0].value, "my_label");
+    }
+
+    #[test]
+    fn test_reservation() {
+        let tokens = tokenize("test.jas", 1, "📦buffer 4096").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].t, Some(TokenType::Reservation));
+        assert_eq!(tokens[0].value, "buffer");
+        assert_eq!(tokens[1].t, Some(TokenType::Memory));
+    }
+
+    #[test]
+    fn test_read_only_section() {
+        let tokens = tokenize("test.jas", 1, "📕table 1 2").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].t, Some(TokenType::ReadOnlySection));
+        assert_eq!(tokens[0].value, "table");
+    }
+
     #[test]
     fn test_jump() {
-        let tokens = tokenize("🦘 123").unwrap();
+        let tokens = tokenize("test.jas", 1, "🦘 123").unwrap();
         assert_eq!(tokens.len(), 2);
         assert_eq!(tokens[0].t, Some(TokenType::Jump));
         assert_eq!(tokens[1].t, Some(TokenType::Memory));
@@ -252,17 +627,37 @@ mod test_tokenize {
 
     #[test]
     fn test_jump_if_condition() {
-        let tokens = tokenize("🦘≠ 123").unwrap();
+        let tokens = tokenize("test.jas", 1, "🦘≠ 123").unwrap();
         assert_eq!(tokens.len(), 2);
-        assert_eq!(tokens[0].t, Some(TokenType::JumpIfNotEqual));
+        assert_eq!(tokens[0].t, Some(TokenType::JumpIf));
         assert_eq!(tokens[0].value, "🦘≠");
         assert_eq!(tokens[1].t, Some(TokenType::Memory));
         assert_eq!(tokens[1].value, "123");
     }
 
+    #[test]
+    fn test_all_conditions() {
+        for (condition, _) in CONDITIONS.iter() {
+            let word = format!("🦘{}", condition);
+            let tokens = tokenize("test.jas", 1, &word).unwrap();
+            assert_eq!(tokens[0].t, Some(TokenType::JumpIf), "{}", word);
+        }
+
+        for (condition, _) in CONDITIONS.iter() {
+            let tokens =
+                tokenize("test.jas", 1, &format!("⬅{} ✔{}", condition, condition)).unwrap();
+            assert_eq!(tokens[0].t, Some(TokenType::MoveIf));
+            assert_eq!(tokens[1].t, Some(TokenType::SetIf));
+        }
+
+        let tokens = tokenize("test.jas", 1, "🦘🔵0 🦘u").unwrap();
+        assert_eq!(tokens[0].t, Some(TokenType::JumpIfCounterZero));
+        assert_eq!(tokens[1].t, Some(TokenType::ConstantReference));
+    }
+
     #[test]
     fn test_call() {
-        let tokens = tokenize("📞 123").unwrap();
+        let tokens = tokenize("test.jas", 1, "📞 123").unwrap();
         assert_eq!(tokens.len(), 2);
         assert_eq!(tokens[0].t, Some(TokenType::Call));
         assert_eq!(tokens[1].t, Some(TokenType::Memory));
@@ -271,60 +666,242 @@ mod test_tokenize {
 
     #[test]
     fn test_return() {
-        let tokens = tokenize("↩").unwrap();
+        let tokens = tokenize("test.jas", 1, "↩").unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].t, Some(TokenType::Return));
     }
 
     #[test]
     fn test_compare_registers() {
-        let tokens = tokenize("⚖ ⚪ ⚫").unwrap();
+        let tokens = tokenize("test.jas", 1, "⚖ ⚪ ⚫").unwrap();
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0].t, Some(TokenType::Compare));
         assert_eq!(tokens[1].t, Some(TokenType::Register));
         assert_eq!(tokens[2].t, Some(TokenType::Register));
     }
 
+    #[test]
+    fn test_esi_edi() {
+        let tokens = tokenize("test.jas", 1, "🟢 ⬅ $4~🟣").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0].t, Some(TokenType::Register));
+        assert_eq!(tokens[0].value, "🟢");
+        assert_eq!(tokens[3].t, Some(TokenType::Register));
+        assert_eq!(tokens[3].value, "🟣");
+    }
+
+    #[test]
+    fn test_sized_registers() {
+        let tokens = tokenize("test.jas", 1, "⚪8 ⬅0 $0~🔵~📏8").unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0].t, Some(TokenType::Register));
+        assert_eq!(tokens[0].value, "⚪8");
+        assert_eq!(tokens[1].t, Some(TokenType::MoveZeroExtend));
+        assert_eq!(tokens[4].t, Some(TokenType::DataWidth));
+        assert_eq!(tokens[4].value, "8");
+
+        let tokens = tokenize("test.jas", 1, "🟢16 ⬅± ⚫8").unwrap();
+        assert_eq!(tokens[0].t, Some(TokenType::Register));
+        assert_eq!(tokens[1].t, Some(TokenType::MoveSignExtend));
+        assert_eq!(tokens[2].t, Some(TokenType::Register));
+
+        // there's no 8 bit version of esi
+        let tokens = tokenize("test.jas", 1, "🟢8").unwrap();
+        assert_eq!(tokens[0].t, Some(TokenType::ConstantReference));
+    }
+
     #[test]
     fn test_whitespace1() {
-        let tokens = tokenize("↩        ").unwrap();
+        let tokens = tokenize("test.jas", 1, "↩        ").unwrap();
         verify_ret(&tokens);
     }
 
     #[test]
     fn test_whitespace2() {
-        let tokens = tokenize("    ↩        ").unwrap();
+        let tokens = tokenize("test.jas", 1, "    ↩        ").unwrap();
         verify_ret(&tokens);
     }
 
     #[test]
     fn test_whitespace3() {
-        let tokens = tokenize("⚪ 	➕ $5").unwrap();
+        let tokens = tokenize("test.jas", 1, "⚪ 	➕ $5").unwrap();
         //                        ^ TAB
         verify_add(&tokens);
     }
 
     #[test]
     fn test_comment() {
-        let tokens = tokenize("↩ # some comment").unwrap();
+        let tokens = tokenize("test.jas", 1, "↩ # some comment").unwrap();
         verify_ret(&tokens);
     }
 
     #[test]
     fn test_invalid_value() {
-        let tokens = tokenize("⚪ ⬅ $SYS_EXIT");
+        let tokens = tokenize("test.jas", 1, "⚪ ⬅ $SYS_EXIT");
         assert!(tokens.is_err());
     }
 
     #[test]
     fn test_full_line_comment() {
-        let tokens = tokenize("# ↩ some comment").unwrap();
+        let tokens = tokenize("test.jas", 1, "# ↩ some comment").unwrap();
         assert_eq!(tokens.len(), 0);
     }
 
+    #[test]
+    fn test_span() {
+        let tokens = tokenize("test.jas", 3, "  ⚪ ⬅ $5,   # comment").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            tokens[0].span,
+            Span {
+                file: "test.jas".to_string(),
+                line: 3,
+                start: 3,
+                end: 4,
+            }
+        );
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (5, 6));
+        // the $ is part of the span, the trailing comma is not
+        assert_eq!((tokens[2].span.start, tokens[2].span.end), (7, 9));
+    }
+
+    #[test]
+    fn test_span_offset() {
+        let tokens = tokenize("test.jas", 1, "📥 ST_ARG~⬇").unwrap();
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (3, 9));
+        assert_eq!((tokens[2].span.start, tokens[2].span.end), (10, 11));
+    }
+
+    #[test]
+    fn test_invalid_value_span() {
+        let error = tokenize("test.jas", 7, "⚪ ⬅ $SYS_EXIT").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.span.as_ref().unwrap().line, 7);
+        assert_eq!(diagnostic.span.as_ref().unwrap().start, 5);
+    }
+
+    #[test]
+    fn test_string() {
+        let tokens = tokenize("test.jas", 1, "📗msg \"hi there, #1~\\\"\", 10").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].t, Some(TokenType::String));
+        assert_eq!(tokens[1].value, "\"hi there, #1~\\\"\"");
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (6, 23));
+        assert_eq!(tokens[2].t, Some(TokenType::Memory));
+    }
+
+    #[test]
+    fn test_string_bytes() {
+        assert_eq!(string_bytes("\"hi!\\n\"").unwrap(), b"hi!\n".to_vec());
+        assert_eq!(
+            string_bytes("\"\\t\\0\\x41\\\\\"").unwrap(),
+            b"\t\0A\\".to_vec()
+        );
+        assert_eq!(string_bytes("c\"ok\"").unwrap(), b"ok\0".to_vec());
+        assert_eq!(string_bytes("\"é\"").unwrap(), "é".as_bytes().to_vec());
+        assert_eq!(
+            string_bytes("\"\\q\"").unwrap_err(),
+            "Invalid escape \\q in string"
+        );
+        assert_eq!(
+            string_bytes("\"\\x4\"").unwrap_err(),
+            "Invalid escape \\x4 in string"
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let error = tokenize("test.jas", 1, "📗msg \"hi").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.msg, "Unterminated string");
+        assert_eq!(diagnostic.span.as_ref().unwrap().start, 6);
+    }
+
+    #[test]
+    fn test_invalid_string() {
+        let error = tokenize("test.jas", 1, "📗msg \"a\\qb\"").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.msg, "Invalid escape \\q in string");
+        assert_eq!(diagnostic.span.as_ref().unwrap().start, 6);
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("-1"), Ok(-1));
+        assert_eq!(parse_number("0xff"), Ok(255));
+        assert_eq!(parse_number("-0x10"), Ok(-16));
+        assert_eq!(parse_number("0b1010"), Ok(10));
+        assert_eq!(parse_number("'A'"), Ok(65));
+        assert_eq!(parse_number("'\\n'"), Ok(10));
+        assert_eq!(parse_number("'\\''"), Ok(39));
+        assert_eq!(parse_number("4294967295"), Ok(4294967295));
+        assert_eq!(parse_number("-2147483648"), Ok(-2147483648));
+
+        let too_large = Err("Doesn't fit in 32 bits.".to_string());
+        assert_eq!(parse_number("4294967296"), too_large);
+        assert_eq!(parse_number("-2147483649"), too_large);
+        assert_eq!(parse_number("0x123456789abcdef01"), too_large);
+
+        let invalid = Err("Should be a number.".to_string());
+        assert_eq!(parse_number("0x"), invalid);
+        assert_eq!(parse_number("12a"), invalid);
+        assert_eq!(parse_number("0x-5"), invalid);
+        assert_eq!(parse_number("0b102"), invalid);
+        assert!(parse_number("'ab'").is_err());
+        assert!(parse_number("'\\q'").is_err());
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let tokens = tokenize("test.jas", 1, "📗t -1, 0xff 'A' ' ' ','").unwrap();
+        assert_eq!(tokens.len(), 6);
+        for token in &tokens[1..] {
+            assert_eq!(token.t, Some(TokenType::Memory));
+        }
+        assert_eq!(tokens[4].value, "' '");
+        assert_eq!(tokens[5].value, "','");
+
+        let tokens = tokenize("test.jas", 1, "⚪ ⬅ $'~'").unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[2].t, Some(TokenType::Value));
+        assert_eq!(tokens[2].value, "'~'");
+
+        let tokens = tokenize("test.jas", 1, "⚖ ⚪, $-0b1").unwrap();
+        assert_eq!(tokens[2].t, Some(TokenType::Value));
+        assert_eq!(tokens[2].value, "-0b1");
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        let error = tokenize("test.jas", 1, "⚪ ⬅ $0x100000000").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(
+            diagnostic.msg,
+            "Invalid value: $0x100000000. Doesn't fit in 32 bits."
+        );
+
+        let error = tokenize("test.jas", 1, "📗t 12a").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.msg, "Invalid number: 12a. Should be a number.");
+
+        let error = tokenize("test.jas", 1, "📗t 'a").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.msg, "Unterminated character");
+    }
+
+    #[test]
+    fn test_data_width() {
+        let tokens = tokenize("test.jas", 1, "📗bytes 📏8 1, 2").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1].t, Some(TokenType::DataWidth));
+        assert_eq!(tokens[1].value, "8");
+        assert!(tokenize("test.jas", 1, "📗bytes 📏7 1").is_err());
+    }
+
     #[test]
     fn test_constant_offset() {
-        let tokens = tokenize("📥 offset~⬇").unwrap();
+        let tokens = tokenize("test.jas", 1, "📥 offset~⬇").unwrap();
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0].t, Some(TokenType::Push));
         assert_eq!(tokens[1].t, Some(TokenType::ConstantReference));
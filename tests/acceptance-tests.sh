@@ -46,6 +46,44 @@ compile_and_compare_return () {
     fi
 }
 
+compile_link_and_compare_return () {
+    SRC="${1}"
+    EXPECTED="${2}"
+
+    target/debug/jvo-asm --emit obj -o a.o "${SRC}"
+    ld -m elf_i386 -o a.out a.o
+
+    # the binary is allowed to return non-zero without stopping the tests
+    set +e
+    ./a.out
+    RETURN="${?}"
+    set -e
+
+    if [ "${RETURN}" -ne "${EXPECTED}" ]; then
+        fail "${SRC}" "${RETURN}" "${EXPECTED}"
+    fi
+}
+
+compile_link_objects_and_compare_return () {
+    SRC="${1}"
+    OTHER_SRC="${2}"
+    EXPECTED="${3}"
+
+    target/debug/jvo-asm --emit obj -o a.o "${SRC}"
+    target/debug/jvo-asm --emit obj -o b.o "${OTHER_SRC}"
+    ld -m elf_i386 -o a.out a.o b.o
+
+    # the binary is allowed to return non-zero without stopping the tests
+    set +e
+    ./a.out
+    RETURN="${?}"
+    set -e
+
+    if [ "${RETURN}" -ne "${EXPECTED}" ]; then
+        fail "${SRC}" "${RETURN}" "${EXPECTED}"
+    fi
+}
+
 cargo build
 compile_and_compare_stdout 'examples/print.jas' 'hi!'
 compile_and_compare_return 'examples/base_ptr_addressing.jas' '4'
@@ -53,5 +91,17 @@ compile_and_compare_return 'examples/multiple_data_sections.jas' '6'
 compile_and_compare_return 'examples/find_max.jas' '222'
 compile_and_compare_return 'examples/square.jas' '49'
 compile_and_compare_return 'examples/factorial.jas' '120'
+compile_and_compare_return 'examples/store.jas' '42'
+compile_and_compare_return 'examples/average.jas' '174'
+compile_and_compare_return 'examples/bytes.jas' '236'
+compile_and_compare_return 'examples/label_addresses.jas' '42'
+compile_and_compare_return 'examples/jump_table.jas' '42'
+compile_and_compare_return 'examples/unsigned.jas' '3'
+compile_and_compare_return 'examples/branchless.jas' '43'
+compile_link_and_compare_return 'examples/find_max.jas' '222'
+compile_link_and_compare_return 'examples/factorial.jas' '120'
+compile_link_and_compare_return 'examples/label_addresses.jas' '42'
+compile_link_and_compare_return 'examples/jump_table.jas' '42'
+compile_link_objects_and_compare_return 'examples/link_main.jas' 'examples/sum_to.jas' '30'
 
 exit $FAILED